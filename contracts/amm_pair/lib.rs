@@ -0,0 +1,944 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+use ink::env::Environment;
+use pidchat_psp22::PSP22Error;
+type DefaultBalance = <ink::env::DefaultEnvironment as Environment>::Balance;
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum AmmError {
+    IdenticalTokens,
+    InvalidToken,
+    InsufficientLiquidity,
+    InsufficientInputAmount,
+    InsufficientOutputAmount,
+    SlippageExceeded,
+    Overflow,
+    TokenCallFailed,
+    Token(PSP22Error),
+    LpToken(PSP22Error),
+}
+
+// Swap fee of 0.3%, as the part of the input kept in per mille
+const FEE_NUMERATOR: DefaultBalance = 997;
+const FEE_DENOMINATOR: DefaultBalance = 1_000;
+
+/// LP shares locked forever by the first deposit, so the share price cannot be inflated
+/// to round later deposits down to zero shares.
+pub const MINIMUM_LIQUIDITY: DefaultBalance = 1_000;
+
+/// Output of a swap of `amount_in` against the given reserves, after the 0.3% fee and
+/// rounded down.
+pub fn get_amount_out(amount_in: DefaultBalance, reserve_in: DefaultBalance, reserve_out: DefaultBalance) -> Result<DefaultBalance, AmmError> {
+    if amount_in == 0 {
+        return Err(AmmError::InsufficientInputAmount);
+    }
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(AmmError::InsufficientLiquidity);
+    }
+    let amount_in_with_fee = amount_in.checked_mul(FEE_NUMERATOR).ok_or(AmmError::Overflow)?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out).ok_or(AmmError::Overflow)?;
+    let denominator = reserve_in
+        .checked_mul(FEE_DENOMINATOR)
+        .and_then(|reserve| reserve.checked_add(amount_in_with_fee))
+        .ok_or(AmmError::Overflow)?;
+    numerator.checked_div(denominator).ok_or(AmmError::Overflow)
+}
+
+/// Amount of the other token matching `amount_a` at the current price, rounded down.
+pub fn quote_amount(amount_a: DefaultBalance, reserve_a: DefaultBalance, reserve_b: DefaultBalance) -> Result<DefaultBalance, AmmError> {
+    if reserve_a == 0 || reserve_b == 0 {
+        return Err(AmmError::InsufficientLiquidity);
+    }
+    amount_a
+        .checked_mul(reserve_b)
+        .ok_or(AmmError::Overflow)?
+        .checked_div(reserve_a)
+        .ok_or(AmmError::Overflow)
+}
+
+/// Integer square root, rounded down.
+pub fn sqrt(value: DefaultBalance) -> DefaultBalance {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method from an estimate above the root
+    let mut current = value;
+    let mut next = value.saturating_div(2).saturating_add(1);
+    while next < current {
+        current = next;
+        next = value.checked_div(next).unwrap_or(0).saturating_add(next).saturating_div(2);
+    }
+    current
+}
+
+#[ink::contract]
+pub mod amm_pair {
+    use super::{
+        get_amount_out,
+        quote_amount,
+        sqrt,
+        AmmError,
+        MINIMUM_LIQUIDITY,
+    };
+    use ink::codegen::EmitEvent;
+    #[cfg(not(test))]
+    use ink::codegen::TraitCallBuilder;
+    #[cfg(not(test))]
+    use ink::env::call::FromAccountId;
+    use ink::prelude::vec::Vec;
+    use ink::reflect::ContractEventBase;
+    use ink_storage::Mapping;
+    use pidchat_psp22::psp22::Psp22;
+    #[cfg(not(test))]
+    use pidchat_psp22::token::PidChatPSP22Ref;
+    use pidchat_psp22::{
+        history,
+        HistoryRecord,
+        PSP22Error,
+    };
+
+    /// Constant-product pair of two PSP22 tokens. The pair is itself the PSP22 token of its
+    /// liquidity shares, whose history and `Transfer` events follow the token: every mint,
+    /// burn and transfer of shares takes an id and is recorded, with the pair as counterparty
+    /// of mints and burns.
+    #[ink(storage)]
+    pub struct AmmPair {
+        token_0: AccountId,
+        token_1: AccountId,
+        reserve_0: Balance,
+        reserve_1: Balance,
+        balances: Mapping<AccountId, Balance>,
+        allowances: Mapping<(AccountId, AccountId), Balance>,
+        total_supply: Balance,
+        decimals: u8,
+        // Ring buffer of the last `history::MAX_HISTORY_SIZE` records of each account
+        history: Mapping<(AccountId, u32), HistoryRecord>,
+        // Number of records ever appended to each account's history
+        history_count: Mapping<AccountId, u32>,
+        last_transfer_id: u64,
+    }
+
+    // Define the Transfer event
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+        id: u64,
+    }
+
+    // Define the Approval event
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: Balance,
+    }
+
+    // Define the LiquidityAdded event
+    #[ink(event)]
+    pub struct LiquidityAdded {
+        #[ink(topic)]
+        provider: AccountId,
+        amount_0: Balance,
+        amount_1: Balance,
+        liquidity: Balance,
+    }
+
+    // Define the LiquidityRemoved event
+    #[ink(event)]
+    pub struct LiquidityRemoved {
+        #[ink(topic)]
+        provider: AccountId,
+        amount_0: Balance,
+        amount_1: Balance,
+        liquidity: Balance,
+    }
+
+    // Define the Swap event
+    #[ink(event)]
+    pub struct Swap {
+        #[ink(topic)]
+        sender: AccountId,
+        #[ink(topic)]
+        token_in: AccountId,
+        amount_in: Balance,
+        amount_out: Balance,
+    }
+
+    impl AmmPair {
+        /// Creates the pair of `token_0` and `token_1`. Shares are minted as the geometric mean
+        /// of the deposits, so `decimals` is usually the mean of the two tokens' decimals.
+        #[ink(constructor)]
+        pub fn new(token_0: AccountId, token_1: AccountId, decimals: u8) -> Result<Self, AmmError> {
+            if token_0 == token_1 {
+                return Err(AmmError::IdenticalTokens);
+            }
+            Ok(Self {
+                token_0,
+                token_1,
+                reserve_0: 0,
+                reserve_1: 0,
+                balances: Mapping::new(),
+                allowances: Mapping::new(),
+                total_supply: 0,
+                decimals,
+                history: Mapping::new(),
+                history_count: Mapping::new(),
+                last_transfer_id: 0,
+            })
+        }
+
+        /// Deposits up to the desired amounts at the current price, at least the minimum
+        /// amounts, and mints LP shares to the caller. Both tokens must be approved to the
+        /// pair. Returns the minted shares.
+        #[ink(message)]
+        pub fn add_liquidity(
+            &mut self,
+            amount_0_desired: Balance,
+            amount_1_desired: Balance,
+            amount_0_min: Balance,
+            amount_1_min: Balance,
+        ) -> Result<Balance, AmmError> {
+            let (amount_0, amount_1) = self.liquidity_amounts(amount_0_desired, amount_1_desired)?;
+            if amount_0 < amount_0_min || amount_1 < amount_1_min {
+                return Err(AmmError::SlippageExceeded);
+            }
+            let provider = self.env().caller();
+            let liquidity = self.mint_liquidity(provider, amount_0, amount_1)?;
+            let pair = self.env().account_id();
+            self.token_transfer_from(self.token_0, provider, pair, amount_0)?;
+            self.token_transfer_from(self.token_1, provider, pair, amount_1)?;
+            Ok(liquidity)
+        }
+
+        /// Burns `liquidity` shares of the caller and pays out the matching part of both
+        /// reserves, at least the minimum amounts.
+        #[ink(message)]
+        pub fn remove_liquidity(
+            &mut self,
+            liquidity: Balance,
+            amount_0_min: Balance,
+            amount_1_min: Balance,
+        ) -> Result<(Balance, Balance), AmmError> {
+            let provider = self.env().caller();
+            let (amount_0, amount_1) = self.burn_liquidity(provider, liquidity)?;
+            if amount_0 < amount_0_min || amount_1 < amount_1_min {
+                return Err(AmmError::SlippageExceeded);
+            }
+            self.token_transfer(self.token_0, provider, amount_0)?;
+            self.token_transfer(self.token_1, provider, amount_1)?;
+            Ok((amount_0, amount_1))
+        }
+
+        /// Swaps exactly `amount_in` of `token_in` for at least `min_amount_out` of the other
+        /// token. `token_in` must be approved to the pair. Returns the output amount.
+        #[ink(message)]
+        pub fn swap_exact_in(&mut self, token_in: AccountId, amount_in: Balance, min_amount_out: Balance) -> Result<Balance, AmmError> {
+            let amount_out = self.apply_swap(token_in, amount_in)?;
+            if amount_out < min_amount_out {
+                return Err(AmmError::SlippageExceeded);
+            }
+            let sender = self.env().caller();
+            let token_out = if token_in == self.token_0 { self.token_1 } else { self.token_0 };
+            self.token_transfer_from(token_in, sender, self.env().account_id(), amount_in)?;
+            self.token_transfer(token_out, sender, amount_out)?;
+            self.emit_event(Swap {
+                sender,
+                token_in,
+                amount_in,
+                amount_out,
+            });
+            Ok(amount_out)
+        }
+
+        #[ink(message)]
+        pub fn get_reserves(&self) -> (Balance, Balance) {
+            (self.reserve_0, self.reserve_1)
+        }
+
+        /// Output of `swap_exact_in` for `amount_in` of `token_in` at the current reserves.
+        #[ink(message)]
+        pub fn quote(&self, token_in: AccountId, amount_in: Balance) -> Result<Balance, AmmError> {
+            let (reserve_in, reserve_out) = self.reserves_for(token_in)?;
+            get_amount_out(amount_in, reserve_in, reserve_out)
+        }
+
+        #[ink(message)]
+        pub fn tokens(&self) -> (AccountId, AccountId) {
+            (self.token_0, self.token_1)
+        }
+
+        // Helper function to order the reserves by swap direction
+        fn reserves_for(&self, token_in: AccountId) -> Result<(Balance, Balance), AmmError> {
+            if token_in == self.token_0 {
+                Ok((self.reserve_0, self.reserve_1))
+            } else if token_in == self.token_1 {
+                Ok((self.reserve_1, self.reserve_0))
+            } else {
+                Err(AmmError::InvalidToken)
+            }
+        }
+
+        // Helper function to pick deposit amounts at the current price, as close as possible
+        // to the desired ones
+        fn liquidity_amounts(&self, amount_0_desired: Balance, amount_1_desired: Balance) -> Result<(Balance, Balance), AmmError> {
+            if self.reserve_0 == 0 && self.reserve_1 == 0 {
+                return Ok((amount_0_desired, amount_1_desired));
+            }
+            let amount_1_optimal = quote_amount(amount_0_desired, self.reserve_0, self.reserve_1)?;
+            if amount_1_optimal <= amount_1_desired {
+                return Ok((amount_0_desired, amount_1_optimal));
+            }
+            let amount_0_optimal = quote_amount(amount_1_desired, self.reserve_1, self.reserve_0)?;
+            Ok((amount_0_optimal, amount_1_desired))
+        }
+
+        // Helper function to account for a deposit, minting shares rounded down
+        fn mint_liquidity(&mut self, provider: AccountId, amount_0: Balance, amount_1: Balance) -> Result<Balance, AmmError> {
+            let liquidity = if self.total_supply == 0 {
+                let liquidity = sqrt(amount_0.checked_mul(amount_1).ok_or(AmmError::Overflow)?)
+                    .checked_sub(MINIMUM_LIQUIDITY)
+                    .ok_or(AmmError::InsufficientLiquidity)?;
+                // Locked on the pair itself
+                self.mint_shares(self.env().account_id(), MINIMUM_LIQUIDITY)?;
+                liquidity
+            } else {
+                let share_0 = quote_amount(amount_0, self.reserve_0, self.total_supply)?;
+                let share_1 = quote_amount(amount_1, self.reserve_1, self.total_supply)?;
+                share_0.min(share_1)
+            };
+            if liquidity == 0 {
+                return Err(AmmError::InsufficientLiquidity);
+            }
+            self.mint_shares(provider, liquidity)?;
+            self.reserve_0 = self.reserve_0.checked_add(amount_0).ok_or(AmmError::Overflow)?;
+            self.reserve_1 = self.reserve_1.checked_add(amount_1).ok_or(AmmError::Overflow)?;
+            self.emit_event(LiquidityAdded {
+                provider,
+                amount_0,
+                amount_1,
+                liquidity,
+            });
+            Ok(liquidity)
+        }
+
+        // Helper function to account for a withdrawal, paying out amounts rounded down
+        fn burn_liquidity(&mut self, provider: AccountId, liquidity: Balance) -> Result<(Balance, Balance), AmmError> {
+            let amount_0 = quote_amount(liquidity, self.total_supply, self.reserve_0)?;
+            let amount_1 = quote_amount(liquidity, self.total_supply, self.reserve_1)?;
+            if amount_0 == 0 || amount_1 == 0 {
+                return Err(AmmError::InsufficientLiquidity);
+            }
+            self.burn_shares(provider, liquidity).map_err(AmmError::LpToken)?;
+            self.reserve_0 = self.reserve_0.saturating_sub(amount_0);
+            self.reserve_1 = self.reserve_1.saturating_sub(amount_1);
+            self.emit_event(LiquidityRemoved {
+                provider,
+                amount_0,
+                amount_1,
+                liquidity,
+            });
+            Ok((amount_0, amount_1))
+        }
+
+        // Helper function to account for a swap, returning the output amount
+        fn apply_swap(&mut self, token_in: AccountId, amount_in: Balance) -> Result<Balance, AmmError> {
+            let (reserve_in, reserve_out) = self.reserves_for(token_in)?;
+            let amount_out = get_amount_out(amount_in, reserve_in, reserve_out)?;
+            if amount_out == 0 {
+                return Err(AmmError::InsufficientOutputAmount);
+            }
+            let reserve_in = reserve_in.checked_add(amount_in).ok_or(AmmError::Overflow)?;
+            let reserve_out = reserve_out.saturating_sub(amount_out);
+            if token_in == self.token_0 {
+                self.reserve_0 = reserve_in;
+                self.reserve_1 = reserve_out;
+            } else {
+                self.reserve_1 = reserve_in;
+                self.reserve_0 = reserve_out;
+            }
+            Ok(amount_out)
+        }
+
+        // Helper function to pull tokens with `transfer_from`
+        #[cfg(not(test))]
+        fn token_transfer_from(&self, token: AccountId, from: AccountId, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            let mut token: PidChatPSP22Ref = FromAccountId::from_account_id(token);
+            token
+                .call_mut()
+                .transfer_from(from, to, value)
+                .try_invoke()
+                .map_err(|_| AmmError::TokenCallFailed)?
+                .map_err(|_| AmmError::TokenCallFailed)?
+                .map_err(AmmError::Token)
+        }
+
+        // Helper function to pay out tokens held by the pair
+        #[cfg(not(test))]
+        fn token_transfer(&self, token: AccountId, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            let mut token: PidChatPSP22Ref = FromAccountId::from_account_id(token);
+            token
+                .call_mut()
+                .transfer(to, value)
+                .try_invoke()
+                .map_err(|_| AmmError::TokenCallFailed)?
+                .map_err(|_| AmmError::TokenCallFailed)?
+                .map_err(AmmError::Token)
+        }
+
+        // Token calls are not available off-chain, tests run them against a fake ledger
+        #[cfg(test)]
+        fn token_transfer_from(&self, token: AccountId, from: AccountId, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            tests::fake_transfer(token, from, to, value)
+        }
+
+        #[cfg(test)]
+        fn token_transfer(&self, token: AccountId, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            tests::fake_transfer(token, self.env().account_id(), to, value)
+        }
+
+        // Helper function to mint LP shares
+        fn mint_shares(&mut self, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            self.total_supply = self.total_supply.checked_add(value).ok_or(AmmError::Overflow)?;
+            self.balances.insert(to, &self.balance_of(to).saturating_add(value));
+            let id = self.record_transfer(self.env().account_id(), to, value);
+            self.emit_event(Transfer {
+                from: None,
+                to: Some(to),
+                value,
+                id,
+            });
+            Ok(())
+        }
+
+        // Helper function to burn LP shares
+        fn burn_shares(&mut self, from: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let balance = self.balance_of(from);
+            if balance < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.balances.insert(from, &balance.saturating_sub(value));
+            self.total_supply = self.total_supply.saturating_sub(value);
+            let id = self.record_transfer(from, self.env().account_id(), value);
+            self.emit_event(Transfer {
+                from: Some(from),
+                to: None,
+                value,
+                id,
+            });
+            Ok(())
+        }
+
+        // Helper function to move LP shares between accounts
+        fn transfer_shares(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let from_balance = self.balance_of(from);
+            if from_balance < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.balances.insert(from, &from_balance.saturating_sub(value));
+            self.balances.insert(to, &self.balance_of(to).saturating_add(value));
+            let id = self.record_transfer(from, to, value);
+            self.emit_event(Transfer {
+                from: Some(from),
+                to: Some(to),
+                value,
+                id,
+            });
+            Ok(())
+        }
+
+        // Helper function to record a movement of shares in the history of both sides,
+        // returns its id
+        fn record_transfer(&mut self, from: AccountId, to: AccountId, value: Balance) -> u64 {
+            self.last_transfer_id = self.last_transfer_id.saturating_add(1);
+            let record = HistoryRecord {
+                id: self.last_transfer_id,
+                from,
+                to,
+                value,
+                timestamp: self.env().block_timestamp(),
+                memo: Vec::new(),
+            };
+            self.push_history(from, record.clone());
+            if to != from {
+                self.push_history(to, record);
+            }
+            self.last_transfer_id
+        }
+
+        // Helper function to append a record to an account's history
+        fn push_history(&mut self, account: AccountId, record: HistoryRecord) {
+            history::push(&mut self.history, &mut self.history_count, account, &record);
+        }
+
+        // Helper function to read an account's history, oldest first
+        fn read_history(&self, account: AccountId) -> Vec<HistoryRecord> {
+            history::read(&self.history, &self.history_count, account)
+        }
+
+        // Helper function to write an allowance and emit the Approval event
+        fn set_allowance(&mut self, owner: AccountId, spender: AccountId, value: Balance) {
+            self.allowances.insert((owner, spender), &value);
+            self.emit_event(Approval { owner, spender, value });
+        }
+
+        // Helper function to emit events. The token contract linked as a dependency also
+        // implements `EmitEvent`, so the contract has to be named explicitly.
+        fn emit_event<Event>(&self, event: Event)
+        where
+            Event: Into<<AmmPair as ContractEventBase>::Type>,
+        {
+            EmitEvent::<AmmPair>::emit_event(self.env(), event);
+        }
+    }
+
+    impl Psp22 for AmmPair {
+        #[ink(message)]
+        fn token_name(&self) -> Vec<u8> {
+            b"PidChat LP".to_vec()
+        }
+
+        #[ink(message)]
+        fn token_symbol(&self) -> Vec<u8> {
+            b"PID-LP".to_vec()
+        }
+
+        #[ink(message)]
+        fn token_decimals(&self) -> u8 {
+            self.decimals
+        }
+
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            self.total_supply
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(owner).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get((owner, spender)).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            self.transfer_shares(self.env().caller(), to, value)
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(from, caller);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.transfer_shares(from, to, value)?;
+            self.set_allowance(from, caller, allowance.saturating_sub(value));
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            self.set_allowance(self.env().caller(), spender, value);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            self.set_allowance(caller, spender, self.allowance(caller, spender).saturating_add(value));
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(caller, spender);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.set_allowance(caller, spender, allowance.saturating_sub(value));
+            Ok(())
+        }
+
+        /// Burning shares outside `remove_liquidity` would forfeit the underlying tokens, so
+        /// it is not supported.
+        #[ink(message)]
+        fn burn(&mut self, _from: AccountId, _value: Balance) -> Result<(), PSP22Error> {
+            Err(PSP22Error::Unauthorized)
+        }
+
+        #[ink(message)]
+        fn history(&self, page: u32, limit: u32) -> Vec<HistoryRecord> {
+            let history = self.read_history(self.env().caller());
+            let start = (page.saturating_sub(1) as usize).saturating_mul(limit as usize);
+            if page == 0 || start >= history.len() {
+                return Vec::new();
+            }
+            let end = start.saturating_add(limit as usize).min(history.len());
+            history[start..end].to_vec()
+        }
+    }
+
+    #[cfg(test)]
+    #[allow(clippy::arithmetic_side_effects)]
+    mod tests {
+        use super::*;
+
+        use core::cell::RefCell;
+        use ink::env::test::{default_accounts, recorded_events, set_callee, set_caller};
+        use ink::env::DefaultEnvironment;
+        use ink::prelude::collections::BTreeMap;
+
+        type Event = <AmmPair as ContractEventBase>::Type;
+
+        // Addresses of the pair and its tokens in tests
+        const PAIR: [u8; 32] = [0xAB; 32];
+        const TOKEN_0: [u8; 32] = [0xB0; 32];
+        const TOKEN_1: [u8; 32] = [0xB1; 32];
+
+        thread_local! {
+            // Balances of the fake tokens, by token and holder
+            static LEDGER: RefCell<BTreeMap<(AccountId, AccountId), Balance>> = const { RefCell::new(BTreeMap::new()) };
+        }
+
+        // Helper function standing in for the token calls of the pair. Allowances are not
+        // checked, a short balance fails like the token does.
+        pub(super) fn fake_transfer(token: AccountId, from: AccountId, to: AccountId, value: Balance) -> Result<(), AmmError> {
+            LEDGER.with(|ledger| {
+                let mut ledger = ledger.borrow_mut();
+                let from_balance = ledger.get(&(token, from)).copied().unwrap_or(0);
+                if from_balance < value {
+                    return Err(AmmError::Token(PSP22Error::InsufficientBalance));
+                }
+                ledger.insert((token, from), from_balance - value);
+                *ledger.entry((token, to)).or_insert(0) += value;
+                Ok(())
+            })
+        }
+
+        // Helper function to read a fake token balance
+        fn token_balance(token: [u8; 32], owner: AccountId) -> Balance {
+            LEDGER.with(|ledger| ledger.borrow().get(&(AccountId::from(token), owner)).copied().unwrap_or(0))
+        }
+
+        // Helper function to credit a fake token balance
+        fn fund(token: [u8; 32], owner: AccountId, value: Balance) {
+            LEDGER.with(|ledger| *ledger.borrow_mut().entry((AccountId::from(token), owner)).or_insert(0) += value);
+        }
+
+        // Helper function to deploy a pair
+        fn setup() -> AmmPair {
+            set_callee::<DefaultEnvironment>(AccountId::from(PAIR));
+            AmmPair::new(AccountId::from(TOKEN_0), AccountId::from(TOKEN_1), 13).unwrap()
+        }
+
+        // Test swap outputs against known values and the rounding direction
+        #[ink::test]
+        fn test_get_amount_out() {
+            // 1_000 in against 1_000_000 / 1_000_000: 997_000_000_000 / 1_000_997_000
+            assert_eq!(get_amount_out(1_000, 1_000_000, 1_000_000), Ok(996));
+            assert_eq!(get_amount_out(0, 1_000, 1_000), Err(AmmError::InsufficientInputAmount));
+            assert_eq!(get_amount_out(1, 0, 1_000), Err(AmmError::InsufficientLiquidity));
+            assert_eq!(get_amount_out(Balance::MAX, 1, 1), Err(AmmError::Overflow));
+            assert_eq!(sqrt(0), 0);
+            assert_eq!(sqrt(15), 3);
+            assert_eq!(sqrt(16), 4);
+            assert_eq!(sqrt(Balance::MAX), u64::MAX as Balance);
+        }
+
+        // Test the constant product never decreases across swaps
+        #[ink::test]
+        fn test_k_invariant() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(pair.mint_liquidity(accounts.alice, 5_000_000, 2_000_000).is_ok());
+
+            for (token, amount) in [(TOKEN_0, 123_457), (TOKEN_1, 1), (TOKEN_1, 999_999), (TOKEN_0, 7)] {
+                let (reserve_0, reserve_1) = pair.get_reserves();
+                let k_before = reserve_0 * reserve_1;
+                let quoted = pair.quote(AccountId::from(token), amount);
+                match pair.apply_swap(AccountId::from(token), amount) {
+                    Ok(amount_out) => assert_eq!(Ok(amount_out), quoted),
+                    Err(error) => assert_eq!(error, AmmError::InsufficientOutputAmount),
+                }
+                let (reserve_0, reserve_1) = pair.get_reserves();
+                assert!(reserve_0 * reserve_1 >= k_before);
+            }
+            assert_eq!(pair.apply_swap(accounts.bob, 1_000), Err(AmmError::InvalidToken));
+        }
+
+        // Test deposits and withdrawals round in favour of the pair
+        #[ink::test]
+        fn test_liquidity_rounding() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // sqrt(4_000_000 * 1_000_000) minus the locked minimum
+            assert_eq!(pair.mint_liquidity(accounts.alice, 4_000_000, 1_000_000), Ok(1_999_000));
+            assert_eq!(pair.balance_of(AccountId::from(PAIR)), MINIMUM_LIQUIDITY);
+
+            // Bob deposits at the current price of 4:1, token_1 bounds the deposit
+            assert_eq!(pair.liquidity_amounts(1_200_000, 250_000), Ok((1_000_000, 250_000)));
+            assert_eq!(pair.liquidity_amounts(1_000_003, 250_001), Ok((1_000_003, 250_000)));
+            // Shares round down: the 3 extra units of token_0 buy nothing
+            assert_eq!(pair.mint_liquidity(accounts.bob, 1_000_003, 250_000), Ok(500_000));
+            let (amount_0, amount_1) = pair.burn_liquidity(accounts.bob, 500_000).unwrap();
+            assert!(amount_0 <= 1_000_003 && amount_1 <= 250_000);
+
+            // Dust deposits mint nothing and are rejected
+            assert_eq!(pair.mint_liquidity(accounts.bob, 1, 1), Err(AmmError::InsufficientLiquidity));
+            assert_eq!(pair.burn_liquidity(accounts.alice, 1), Err(AmmError::InsufficientLiquidity));
+            assert_eq!(pair.burn_liquidity(accounts.bob, 1_000), Err(AmmError::LpToken(PSP22Error::InsufficientBalance)));
+
+            // Alice takes out at most what is backing her shares
+            let (reserve_0, reserve_1) = pair.get_reserves();
+            let (amount_0, amount_1) = pair.burn_liquidity(accounts.alice, 1_999_000).unwrap();
+            assert!(amount_0 < reserve_0 && amount_1 < reserve_1);
+            assert_eq!(pair.total_supply(), MINIMUM_LIQUIDITY);
+        }
+
+        // Test LP shares behave as a PSP22 token
+        #[ink::test]
+        fn test_lp_transfer() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(pair.mint_liquidity(accounts.alice, 1_000_000, 1_000_000).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(pair.transfer(accounts.bob, 1_000).is_ok());
+            assert!(pair.approve(accounts.charlie, 500).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert!(pair.transfer_from(accounts.alice, accounts.charlie, 500).is_ok());
+            assert_eq!(pair.balance_of(accounts.alice), 997_500);
+            assert_eq!(pair.allowance(accounts.alice, accounts.charlie), 0);
+            assert_eq!(pair.burn(accounts.charlie, 500), Err(PSP22Error::Unauthorized));
+        }
+
+        // Test add_liquidity pulls both tokens and mints shares through the message
+        #[ink::test]
+        fn test_add_liquidity_message() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let pair_account = AccountId::from(PAIR);
+            fund(TOKEN_0, accounts.alice, 4_000_000);
+            fund(TOKEN_1, accounts.alice, 1_000_000);
+            fund(TOKEN_0, accounts.bob, 1_000_000);
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(pair.add_liquidity(4_000_000, 1_000_000, 0, 0), Ok(1_999_000));
+            assert_eq!(token_balance(TOKEN_0, pair_account), 4_000_000);
+            assert_eq!(token_balance(TOKEN_1, pair_account), 1_000_000);
+            assert_eq!(token_balance(TOKEN_0, accounts.alice), 0);
+            assert_eq!(pair.get_reserves(), (4_000_000, 1_000_000));
+            assert_eq!(pair.balance_of(accounts.alice), 1_999_000);
+            assert_eq!(pair.token_decimals(), 13);
+
+            // The price bounds the deposit before any token is pulled
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(pair.add_liquidity(1_000_000, 300_000, 0, 260_000), Err(AmmError::SlippageExceeded));
+            assert_eq!(token_balance(TOKEN_0, accounts.bob), 1_000_000);
+            // Bob holds no token_1, the token call fails and the message reverts on-chain
+            assert_eq!(
+                pair.add_liquidity(1_000_000, 250_000, 0, 0),
+                Err(AmmError::Token(PSP22Error::InsufficientBalance))
+            );
+        }
+
+        // Test swap_exact_in moves the tokens both ways and emits Swap
+        #[ink::test]
+        fn test_swap_exact_in_message() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let pair_account = AccountId::from(PAIR);
+            fund(TOKEN_0, accounts.alice, 1_000_000);
+            fund(TOKEN_1, accounts.alice, 1_000_000);
+            fund(TOKEN_0, accounts.bob, 1_000);
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(pair.add_liquidity(1_000_000, 1_000_000, 0, 0).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let token_0 = AccountId::from(TOKEN_0);
+            assert_eq!(pair.swap_exact_in(token_0, 1_000, 996), Ok(996));
+            assert_eq!(token_balance(TOKEN_0, accounts.bob), 0);
+            assert_eq!(token_balance(TOKEN_1, accounts.bob), 996);
+            assert_eq!(token_balance(TOKEN_0, pair_account), 1_001_000);
+            assert_eq!(token_balance(TOKEN_1, pair_account), 999_004);
+            assert_eq!(pair.get_reserves(), (1_001_000, 999_004));
+            assert_eq!(pair.swap_exact_in(accounts.bob, 1_000, 0), Err(AmmError::InvalidToken));
+            assert_eq!(pair.swap_exact_in(AccountId::from(TOKEN_1), 996, 996), Err(AmmError::SlippageExceeded));
+
+            let swap = recorded_events().find_map(|event| {
+                match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::Swap(swap)) => Some(swap),
+                    _ => None,
+                }
+            });
+            let swap = swap.expect("Swap event");
+            assert_eq!((swap.sender, swap.token_in, swap.amount_in, swap.amount_out), (accounts.bob, token_0, 1_000, 996));
+        }
+
+        // Test mints and burns of shares are recorded in the history like transfers
+        #[ink::test]
+        fn test_lp_history() {
+            let mut pair = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let pair_account = AccountId::from(PAIR);
+            assert!(pair.mint_liquidity(accounts.alice, 1_000_000, 1_000_000).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(pair.transfer(accounts.bob, 1_000).is_ok());
+            assert!(pair.burn_liquidity(accounts.alice, 2_000).is_ok());
+
+            // The locked minimum is id 1, Alice's mint id 2
+            let history = pair.history(1, 10);
+            let moves: Vec<_> = history.iter().map(|record| (record.id, record.from, record.to, record.value)).collect();
+            assert_eq!(
+                moves,
+                [
+                    (2, pair_account, accounts.alice, 999_000),
+                    (3, accounts.alice, accounts.bob, 1_000),
+                    (4, accounts.alice, pair_account, 2_000),
+                ]
+            );
+
+            // History keeps the last 100 records
+            for _ in 0..120 {
+                assert!(pair.transfer(accounts.bob, 1).is_ok());
+            }
+            let history = pair.history(1, 200);
+            assert_eq!(history.len(), history::MAX_HISTORY_SIZE as usize);
+            assert_eq!(history[99].id, 124);
+        }
+    }
+
+    // The unit tests run against a fake token ledger, so the token calls are covered here.
+    // Needs a running substrate-contracts-node: `cargo test --features e2e-tests`.
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+        use pidchat_psp22::token::PidChatPSP22Ref;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        // Helper function to deploy two tokens minted to Alice and their pair. Returns
+        // (token_0, token_1, pair).
+        async fn setup(client: &mut ink_e2e::Client<C, E>) -> (AccountId, AccountId, AccountId) {
+            let mut tokens = Vec::new();
+            for symbol in ["PID", "WLUNES"] {
+                let constructor = PidChatPSP22Ref::new(1_000_000, None, Some(symbol.into()), 18);
+                let token = client
+                    .instantiate("pidchat_psp22", &ink_e2e::alice(), constructor, 0, None)
+                    .await
+                    .expect("instantiate failed")
+                    .account_id;
+                tokens.push(token);
+            }
+            let pair = client
+                .instantiate("pidchat_amm_pair", &ink_e2e::alice(), AmmPairRef::new(tokens[0], tokens[1], 18), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            (tokens[0], tokens[1], pair)
+        }
+
+        // Helper function to approve `value` of `token` to the pair as Alice
+        async fn approve(client: &mut ink_e2e::Client<C, E>, token: AccountId, pair: AccountId, value: Balance) {
+            let approve = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.approve(pair, value));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+        }
+
+        // Helper function to read a token balance
+        async fn balance_of(client: &mut ink_e2e::Client<C, E>, token: AccountId, owner: AccountId) -> Balance {
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(owner));
+            client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value()
+        }
+
+        // Helper function to read the reserves of the pair
+        async fn reserves(client: &mut ink_e2e::Client<C, E>, pair: AccountId) -> (Balance, Balance) {
+            let reserves = build_message::<AmmPairRef>(pair).call(|contract| contract.get_reserves());
+            client.call_dry_run(&ink_e2e::alice(), &reserves, 0, None).await.return_value()
+        }
+
+        // Test tokens move with add_liquidity, swap_exact_in and remove_liquidity
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_liquidity_and_swap(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let (token_0, token_1, pair) = setup(&mut client).await;
+
+            approve(&mut client, token_0, pair, 100_000).await;
+            approve(&mut client, token_1, pair, 100_000).await;
+            let add = build_message::<AmmPairRef>(pair).call(|contract| contract.add_liquidity(100_000, 100_000, 0, 0));
+            let liquidity = client
+                .call(&ink_e2e::alice(), add, 0, None)
+                .await
+                .expect("add_liquidity failed")
+                .return_value()
+                .unwrap();
+            assert_eq!(liquidity, 100_000 - MINIMUM_LIQUIDITY);
+            assert_eq!(reserves(&mut client, pair).await, (100_000, 100_000));
+            assert_eq!(balance_of(&mut client, token_0, pair).await, 100_000);
+            assert_eq!(balance_of(&mut client, token_1, pair).await, 100_000);
+
+            let amount_out = get_amount_out(1_000, 100_000, 100_000).unwrap();
+            approve(&mut client, token_0, pair, 1_000).await;
+            let swap = build_message::<AmmPairRef>(pair).call(|contract| contract.swap_exact_in(token_0, 1_000, amount_out));
+            client.call(&ink_e2e::alice(), swap, 0, None).await.expect("swap_exact_in failed");
+            assert_eq!(reserves(&mut client, pair).await, (101_000, 100_000 - amount_out));
+            assert_eq!(balance_of(&mut client, token_0, pair).await, 101_000);
+            assert_eq!(balance_of(&mut client, token_1, alice).await, 900_000 + amount_out);
+
+            let remove = build_message::<AmmPairRef>(pair).call(|contract| contract.remove_liquidity(liquidity, 0, 0));
+            let (amount_0, amount_1) = client
+                .call(&ink_e2e::alice(), remove, 0, None)
+                .await
+                .expect("remove_liquidity failed")
+                .return_value()
+                .unwrap();
+            assert_eq!(reserves(&mut client, pair).await, (101_000 - amount_0, 100_000 - amount_out - amount_1));
+            assert_eq!(balance_of(&mut client, token_0, pair).await, 101_000 - amount_0);
+            assert_eq!(balance_of(&mut client, token_1, alice).await, 900_000 + amount_out + amount_1);
+            Ok(())
+        }
+
+        // Test a failed token call reverts the message, reserves and shares included
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_failed_token_call_rolls_back(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let (token_0, token_1, pair) = setup(&mut client).await;
+
+            // Only token_0 is approved, so pulling token_1 fails after token_0 moved
+            approve(&mut client, token_0, pair, 100_000).await;
+            let add = build_message::<AmmPairRef>(pair).call(|contract| contract.add_liquidity(100_000, 100_000, 0, 0));
+            let result = client.call_dry_run(&ink_e2e::alice(), &add, 0, None).await;
+            assert_eq!(result.return_value(), Err(AmmError::Token(PSP22Error::InsufficientAllowance)));
+            assert!(client.call(&ink_e2e::alice(), add, 0, None).await.is_err());
+            assert_eq!(reserves(&mut client, pair).await, (0, 0));
+            assert_eq!(balance_of(&mut client, token_0, pair).await, 0);
+            let shares = build_message::<AmmPairRef>(pair).call(|contract| contract.total_supply());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &shares, 0, None).await.return_value(), 0);
+
+            // A swap whose input is not approved leaves the reserves unchanged
+            approve(&mut client, token_1, pair, 100_000).await;
+            let add = build_message::<AmmPairRef>(pair).call(|contract| contract.add_liquidity(100_000, 100_000, 0, 0));
+            client.call(&ink_e2e::alice(), add, 0, None).await.expect("add_liquidity failed");
+            let swap = build_message::<AmmPairRef>(pair).call(|contract| contract.swap_exact_in(token_1, 1_000, 0));
+            let result = client.call_dry_run(&ink_e2e::alice(), &swap, 0, None).await;
+            assert_eq!(result.return_value(), Err(AmmError::Token(PSP22Error::InsufficientAllowance)));
+            assert!(client.call(&ink_e2e::alice(), swap, 0, None).await.is_err());
+            assert_eq!(reserves(&mut client, pair).await, (100_000, 100_000));
+            assert_eq!(balance_of(&mut client, token_1, alice).await, 900_000);
+            Ok(())
+        }
+    }
+}
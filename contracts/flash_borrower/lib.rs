@@ -0,0 +1,170 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+
+#[ink::contract]
+pub mod flash_borrower {
+    use ink::codegen::TraitCallBuilder;
+    use ink::env::call::FromAccountId;
+    use ink::prelude::vec::Vec;
+    use pidchat_psp22::flashloan::FlashBorrower as FlashBorrowerHook;
+    use pidchat_psp22::psp22::Psp22;
+    use pidchat_psp22::token::PidChatPSP22Ref;
+    use pidchat_psp22::FlashBorrowerError;
+
+    /// Sample flash loan receiver: accepts loans from `token` only and approves the
+    /// repayment, paying the fee out of its own balance. Real borrowers use the loan
+    /// between receiving it and approving the repayment.
+    ///
+    /// Anyone can start a flash loan to any receiver, so loans not initiated by the owner
+    /// are rejected; otherwise third parties could spend its balance on fees.
+    #[ink(storage)]
+    pub struct FlashBorrower {
+        token: AccountId,
+        owner: AccountId,
+        loans: u32,
+    }
+
+    impl FlashBorrower {
+        /// Creates a borrower of `token` owned by the caller.
+        #[ink(constructor)]
+        pub fn new(token: AccountId) -> Self {
+            Self {
+                token,
+                owner: Self::env().caller(),
+                loans: 0,
+            }
+        }
+
+        #[ink(message)]
+        pub fn token(&self) -> AccountId {
+            self.token
+        }
+
+        /// Only loans initiated by this account are accepted.
+        #[ink(message)]
+        pub fn owner(&self) -> AccountId {
+            self.owner
+        }
+
+        /// Number of flash loans received.
+        #[ink(message)]
+        pub fn loans(&self) -> u32 {
+            self.loans
+        }
+    }
+
+    impl FlashBorrowerHook for FlashBorrower {
+        #[ink(message)]
+        fn on_flashloan(&mut self, initiator: AccountId, amount: Balance, fee: Balance, _data: Vec<u8>) -> Result<(), FlashBorrowerError> {
+            if self.env().caller() != self.token || initiator != self.owner {
+                return Err(FlashBorrowerError::FlashloanRejected);
+            }
+            let repayment = amount.checked_add(fee).ok_or(FlashBorrowerError::FlashloanRejected)?;
+            let mut token: PidChatPSP22Ref = FromAccountId::from_account_id(self.token);
+            token
+                .call_mut()
+                .approve(self.token, repayment)
+                .try_invoke()
+                .map_err(|_| FlashBorrowerError::FlashloanRejected)?
+                .map_err(|_| FlashBorrowerError::FlashloanRejected)?
+                .map_err(|_| FlashBorrowerError::FlashloanRejected)?;
+            self.loans = self.loans.saturating_add(1);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::test::{default_accounts, set_callee, set_caller};
+        use ink::env::DefaultEnvironment;
+
+        // Address of the token contract in tests
+        const TOKEN: [u8; 32] = [0xBB; 32];
+
+        // Test loans are only accepted from the configured token
+        #[ink::test]
+        fn test_rejects_other_lenders() {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_callee::<DefaultEnvironment>(AccountId::from([0xDD; 32]));
+            let mut borrower = FlashBorrower::new(AccountId::from(TOKEN));
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                borrower.on_flashloan(accounts.bob, 1_000, 1, Vec::new()),
+                Err(FlashBorrowerError::FlashloanRejected)
+            );
+            assert_eq!(borrower.loans(), 0);
+        }
+
+        // Test loans initiated by anyone but the owner are rejected
+        #[ink::test]
+        fn test_rejects_foreign_initiator() {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_callee::<DefaultEnvironment>(AccountId::from([0xDD; 32]));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            let mut borrower = FlashBorrower::new(AccountId::from(TOKEN));
+            assert_eq!(borrower.owner(), accounts.alice);
+
+            // The token forwards Bob as the initiator of the loan
+            set_caller::<DefaultEnvironment>(AccountId::from(TOKEN));
+            assert_eq!(
+                borrower.on_flashloan(accounts.bob, 1_000, 1, Vec::new()),
+                Err(FlashBorrowerError::FlashloanRejected)
+            );
+            assert_eq!(borrower.loans(), 0);
+        }
+    }
+
+    // Cross-contract calls cannot be dispatched off-chain, so the hook is covered here.
+    // Needs a running substrate-contracts-node: `cargo test --features e2e-tests`.
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+        use pidchat_psp22::PSP22Error;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        // Test a flash loan repaid through the hook, after one the borrower cannot pay the
+        // fee of
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_flashloan(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let token = client
+                .instantiate("pidchat_psp22", &ink_e2e::alice(), PidChatPSP22Ref::new(1_000_000, None, None, 18), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let borrower = client
+                .instantiate("pidchat_flash_borrower", &ink_e2e::alice(), FlashBorrowerRef::new(token), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let set_fee = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.set_flash_fee_bps(10));
+            client.call(&ink_e2e::alice(), set_fee, 0, None).await.expect("set_flash_fee_bps failed");
+
+            // The hook approves the repayment, but the fee cannot be burned
+            let flashloan = build_message::<PidChatPSP22Ref>(token)
+                .call(|contract| contract.flashloan(borrower, 10_000, Vec::new()));
+            let result = client.call_dry_run(&ink_e2e::alice(), &flashloan, 0, None).await;
+            assert_eq!(result.return_value(), Err(PSP22Error::InsufficientBalance));
+
+            let fund = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.transfer(borrower, 10));
+            client.call(&ink_e2e::alice(), fund, 0, None).await.expect("transfer failed");
+            // Only the owner of the borrower can start a loan to it
+            let result = client.call_dry_run(&ink_e2e::bob(), &flashloan, 0, None).await;
+            assert_eq!(result.return_value(), Err(PSP22Error::FlashloanFailed));
+            client.call(&ink_e2e::alice(), flashloan, 0, None).await.expect("flashloan failed");
+
+            let loans = build_message::<FlashBorrowerRef>(borrower).call(|contract| contract.loans());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &loans, 0, None).await.return_value(), 1);
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(borrower));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 0);
+            let total_supply = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.total_supply());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &total_supply, 0, None).await.return_value(), 1_000_000 - 10);
+            Ok(())
+        }
+    }
+}
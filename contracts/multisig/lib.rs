@@ -0,0 +1,574 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+use ink::env::Environment;
+use pidchat_psp22::PSP22Error;
+type DefaultAccountId = <ink::env::DefaultEnvironment as Environment>::AccountId;
+type DefaultBalance = <ink::env::DefaultEnvironment as Environment>::Balance;
+
+/// Action carried out by a proposal once enough owners confirmed it.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum Action {
+    /// PSP22 `transfer` of the treasury token
+    Transfer { to: DefaultAccountId, value: DefaultBalance },
+    /// PSP22 `approve` of the treasury token
+    Approve { spender: DefaultAccountId, value: DefaultBalance },
+    AddOwner(DefaultAccountId),
+    RemoveOwner(DefaultAccountId),
+    ChangeThreshold(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: DefaultAccountId,
+    pub action: Action,
+    pub executed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum MultisigError {
+    NotOwner,
+    InvalidOwners,
+    InvalidThreshold,
+    OwnerExists,
+    OwnerNotFound,
+    ProposalNotFound,
+    AlreadyExecuted,
+    AlreadyConfirmed,
+    NotConfirmed,
+    ThresholdNotReached,
+    TokenCallFailed,
+    Token(PSP22Error),
+}
+
+#[ink::contract]
+pub mod multisig {
+    use super::{
+        Action,
+        DefaultAccountId,
+        MultisigError,
+        Proposal,
+    };
+    use ink::codegen::{
+        EmitEvent,
+        TraitCallBuilder,
+    };
+    use ink::env::call::FromAccountId;
+    use ink::prelude::vec::Vec;
+    use ink::reflect::ContractEventBase;
+    use ink_storage::Mapping;
+    use pidchat_psp22::psp22::Psp22;
+    use pidchat_psp22::token::PidChatPSP22Ref;
+
+    // Maximum number of owners
+    const MAX_OWNERS: usize = 50;
+
+    /// M-of-N treasury wallet holding the PidChat token.
+    #[ink(storage)]
+    pub struct Multisig {
+        owners: Vec<DefaultAccountId>,
+        threshold: u32,
+        token: DefaultAccountId,
+        proposals: Mapping<u64, Proposal>,
+        confirmations: Mapping<(u64, DefaultAccountId), ()>,
+        last_proposal_id: u64,
+    }
+
+    // Define the Submitted event
+    #[ink(event)]
+    pub struct Submitted {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        proposer: AccountId,
+        action: Action,
+    }
+
+    // Define the Confirmed event
+    #[ink(event)]
+    pub struct Confirmed {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        owner: AccountId,
+    }
+
+    // Define the Revoked event
+    #[ink(event)]
+    pub struct Revoked {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        owner: AccountId,
+    }
+
+    // Define the Executed event
+    #[ink(event)]
+    pub struct Executed {
+        #[ink(topic)]
+        id: u64,
+    }
+
+    // Define the OwnerAdded event
+    #[ink(event)]
+    pub struct OwnerAdded {
+        #[ink(topic)]
+        owner: AccountId,
+    }
+
+    // Define the OwnerRemoved event
+    #[ink(event)]
+    pub struct OwnerRemoved {
+        #[ink(topic)]
+        owner: AccountId,
+    }
+
+    // Define the ThresholdChanged event
+    #[ink(event)]
+    pub struct ThresholdChanged {
+        threshold: u32,
+    }
+
+    impl Multisig {
+        /// Creates a wallet controlled by `owners`, requiring `threshold` confirmations to
+        /// execute a proposal on `token`.
+        #[ink(constructor)]
+        pub fn new(owners: Vec<AccountId>, threshold: u32, token: AccountId) -> Result<Self, MultisigError> {
+            let mut unique = owners.clone();
+            unique.sort();
+            unique.dedup();
+            if owners.is_empty() || owners.len() > MAX_OWNERS || unique.len() != owners.len() {
+                return Err(MultisigError::InvalidOwners);
+            }
+            if threshold == 0 || threshold as usize > owners.len() {
+                return Err(MultisigError::InvalidThreshold);
+            }
+            Ok(Self {
+                owners,
+                threshold,
+                token,
+                proposals: Mapping::new(),
+                confirmations: Mapping::new(),
+                last_proposal_id: 0,
+            })
+        }
+
+        /// Submits a proposal, confirmed by the proposer. Returns the id of the proposal.
+        #[ink(message)]
+        pub fn submit(&mut self, action: Action) -> Result<u64, MultisigError> {
+            let proposer = self.ensure_owner()?;
+            let id = self.last_proposal_id.saturating_add(1);
+            self.proposals.insert(id, &Proposal {
+                id,
+                proposer,
+                action: action.clone(),
+                executed: false,
+            });
+            self.last_proposal_id = id;
+            self.emit_event(Submitted {
+                id,
+                proposer,
+                action,
+            });
+            self.confirmations.insert((id, proposer), &());
+            self.emit_event(Confirmed { id, owner: proposer });
+            Ok(id)
+        }
+
+        #[ink(message)]
+        pub fn confirm(&mut self, id: u64) -> Result<(), MultisigError> {
+            let owner = self.ensure_owner()?;
+            self.ensure_pending(id)?;
+            if self.confirmations.contains((id, owner)) {
+                return Err(MultisigError::AlreadyConfirmed);
+            }
+            self.confirmations.insert((id, owner), &());
+            self.emit_event(Confirmed { id, owner });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn revoke_confirmation(&mut self, id: u64) -> Result<(), MultisigError> {
+            let owner = self.ensure_owner()?;
+            self.ensure_pending(id)?;
+            if !self.confirmations.contains((id, owner)) {
+                return Err(MultisigError::NotConfirmed);
+            }
+            self.confirmations.remove((id, owner));
+            self.emit_event(Revoked { id, owner });
+            Ok(())
+        }
+
+        /// Executes a proposal confirmed by at least `threshold` current owners.
+        #[ink(message)]
+        pub fn execute(&mut self, id: u64) -> Result<(), MultisigError> {
+            self.ensure_owner()?;
+            let mut proposal = self.ensure_pending(id)?;
+            if self.confirmation_count(id) < self.threshold {
+                return Err(MultisigError::ThresholdNotReached);
+            }
+            // Mark as executed first so a re-entrant call cannot execute it twice
+            proposal.executed = true;
+            self.proposals.insert(id, &proposal);
+            match proposal.action {
+                Action::Transfer { to, value } => {
+                    let mut token: PidChatPSP22Ref = FromAccountId::from_account_id(self.token);
+                    token
+                        .call_mut()
+                        .transfer(to, value)
+                        .try_invoke()
+                        .map_err(|_| MultisigError::TokenCallFailed)?
+                        .map_err(|_| MultisigError::TokenCallFailed)?
+                        .map_err(MultisigError::Token)?;
+                }
+                Action::Approve { spender, value } => {
+                    let mut token: PidChatPSP22Ref = FromAccountId::from_account_id(self.token);
+                    token
+                        .call_mut()
+                        .approve(spender, value)
+                        .try_invoke()
+                        .map_err(|_| MultisigError::TokenCallFailed)?
+                        .map_err(|_| MultisigError::TokenCallFailed)?
+                        .map_err(MultisigError::Token)?;
+                }
+                Action::AddOwner(owner) => {
+                    if self.owners.contains(&owner) {
+                        return Err(MultisigError::OwnerExists);
+                    }
+                    if self.owners.len() >= MAX_OWNERS {
+                        return Err(MultisigError::InvalidOwners);
+                    }
+                    self.owners.push(owner);
+                    self.emit_event(OwnerAdded { owner });
+                }
+                Action::RemoveOwner(owner) => {
+                    let index = self
+                        .owners
+                        .iter()
+                        .position(|current| *current == owner)
+                        .ok_or(MultisigError::OwnerNotFound)?;
+                    if self.owners.len() <= self.threshold as usize {
+                        return Err(MultisigError::InvalidThreshold);
+                    }
+                    self.owners.swap_remove(index);
+                    self.emit_event(OwnerRemoved { owner });
+                }
+                Action::ChangeThreshold(threshold) => {
+                    if threshold == 0 || threshold as usize > self.owners.len() {
+                        return Err(MultisigError::InvalidThreshold);
+                    }
+                    self.threshold = threshold;
+                    self.emit_event(ThresholdChanged { threshold });
+                }
+            }
+            self.emit_event(Executed { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn owners(&self) -> Vec<AccountId> {
+            self.owners.clone()
+        }
+
+        #[ink(message)]
+        pub fn threshold(&self) -> u32 {
+            self.threshold
+        }
+
+        #[ink(message)]
+        pub fn token(&self) -> AccountId {
+            self.token
+        }
+
+        #[ink(message)]
+        pub fn get_proposal(&self, id: u64) -> Option<Proposal> {
+            self.proposals.get(id)
+        }
+
+        /// Number of confirmations of a proposal by current owners.
+        #[ink(message)]
+        pub fn confirmation_count(&self, id: u64) -> u32 {
+            let count = self
+                .owners
+                .iter()
+                .filter(|owner| self.confirmations.contains((id, **owner)))
+                .count();
+            u32::try_from(count).unwrap_or(u32::MAX)
+        }
+
+        #[ink(message)]
+        pub fn is_confirmed_by(&self, id: u64, owner: AccountId) -> bool {
+            self.confirmations.contains((id, owner))
+        }
+
+        // Helper function to emit events. The token contract linked as a dependency also
+        // implements `EmitEvent`, so the contract has to be named explicitly.
+        fn emit_event<Event>(&self, event: Event)
+        where
+            Event: Into<<Multisig as ContractEventBase>::Type>,
+        {
+            EmitEvent::<Multisig>::emit_event(self.env(), event);
+        }
+
+        // Helper function to check that the caller is an owner
+        fn ensure_owner(&self) -> Result<AccountId, MultisigError> {
+            let caller = self.env().caller();
+            if !self.owners.contains(&caller) {
+                return Err(MultisigError::NotOwner);
+            }
+            Ok(caller)
+        }
+
+        // Helper function to load a proposal that was not executed yet
+        fn ensure_pending(&self, id: u64) -> Result<Proposal, MultisigError> {
+            let proposal = self.proposals.get(id).ok_or(MultisigError::ProposalNotFound)?;
+            if proposal.executed {
+                return Err(MultisigError::AlreadyExecuted);
+            }
+            Ok(proposal)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::test::{default_accounts, set_callee, set_caller, DefaultAccounts};
+        use ink::env::DefaultEnvironment;
+        use pidchat_psp22::token::PidChatPSP22;
+
+        // Address of the multisig contract in tests
+        const MULTISIG: [u8; 32] = [0xAA; 32];
+        // Address of the token contract in tests
+        const TOKEN: [u8; 32] = [0xBB; 32];
+
+        // Helper function to deploy the token with the treasury minted to the multisig,
+        // then the 2-of-3 multisig owned by Alice, Bob and Charlie
+        fn setup() -> (PidChatPSP22, Multisig, DefaultAccounts<DefaultEnvironment>) {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_caller::<DefaultEnvironment>(AccountId::from(MULTISIG));
+            set_callee::<DefaultEnvironment>(AccountId::from(TOKEN));
+            let token = PidChatPSP22::new(1_000_000, None, None, 18);
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            set_callee::<DefaultEnvironment>(AccountId::from(MULTISIG));
+            let owners = ink::prelude::vec![accounts.alice, accounts.bob, accounts.charlie];
+            let multisig = Multisig::new(owners, 2, AccountId::from(TOKEN)).unwrap();
+            (token, multisig, accounts)
+        }
+
+        // Test constructor validation
+        #[ink::test]
+        fn test_new_validation() {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let token = AccountId::from(TOKEN);
+
+            assert!(matches!(Multisig::new(Vec::new(), 1, token), Err(MultisigError::InvalidOwners)));
+            assert!(matches!(
+                Multisig::new(ink::prelude::vec![accounts.alice, accounts.alice], 1, token),
+                Err(MultisigError::InvalidOwners)
+            ));
+            assert!(matches!(
+                Multisig::new(ink::prelude::vec![accounts.alice], 2, token),
+                Err(MultisigError::InvalidThreshold)
+            ));
+        }
+
+        // Test the treasury is held by the multisig
+        #[ink::test]
+        fn test_treasury_held_by_multisig() {
+            let (token, multisig, accounts) = setup();
+
+            assert_eq!(multisig.token(), AccountId::from(TOKEN));
+            // Contract storage is keyed by the callee in the off-chain environment
+            set_callee::<DefaultEnvironment>(AccountId::from(TOKEN));
+            assert_eq!(token.balance_of(AccountId::from(MULTISIG)), 1_000_000);
+            assert_eq!(token.balance_of(accounts.alice), 0);
+        }
+
+        // Test confirmations of a token transfer proposal
+        #[ink::test]
+        fn test_transfer_proposal_confirmations() {
+            let (_, mut multisig, accounts) = setup();
+
+            let id = multisig.submit(Action::Transfer { to: accounts.django, value: 500 }).unwrap();
+            assert_eq!(multisig.confirmation_count(id), 1);
+            assert_eq!(multisig.confirm(id), Err(MultisigError::AlreadyConfirmed));
+            assert_eq!(multisig.execute(id), Err(MultisigError::ThresholdNotReached));
+
+            // Outsiders cannot take part
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert_eq!(multisig.confirm(id), Err(MultisigError::NotOwner));
+            assert_eq!(multisig.submit(Action::ChangeThreshold(1)), Err(MultisigError::NotOwner));
+
+            // A revoked confirmation no longer counts
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(multisig.confirm(id).is_ok());
+            assert!(multisig.revoke_confirmation(id).is_ok());
+            assert_eq!(multisig.confirmation_count(id), 1);
+            assert!(multisig.confirm(id).is_ok());
+            assert_eq!(multisig.confirmation_count(id), 2);
+        }
+
+        // Test a token transfer made by the multisig account, which is what an executed
+        // transfer proposal dispatches
+        #[ink::test]
+        fn test_token_transfer_from_multisig() {
+            let (mut token, _, accounts) = setup();
+
+            set_caller::<DefaultEnvironment>(AccountId::from(MULTISIG));
+            set_callee::<DefaultEnvironment>(AccountId::from(TOKEN));
+            assert!(token.transfer(accounts.django, 500).is_ok());
+            assert_eq!(token.balance_of(AccountId::from(MULTISIG)), 1_000_000 - 500);
+            assert_eq!(token.balance_of(accounts.django), 500);
+        }
+
+        // Test owner set management through proposals
+        #[ink::test]
+        fn test_owner_management() {
+            let (_, mut multisig, accounts) = setup();
+
+            let add = multisig.submit(Action::AddOwner(accounts.django)).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(multisig.confirm(add).is_ok());
+            assert!(multisig.execute(add).is_ok());
+            assert_eq!(multisig.owners().len(), 4);
+            assert_eq!(multisig.execute(add), Err(MultisigError::AlreadyExecuted));
+
+            let threshold = multisig.submit(Action::ChangeThreshold(3)).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert!(multisig.confirm(threshold).is_ok());
+            assert!(multisig.execute(threshold).is_ok());
+            assert_eq!(multisig.threshold(), 3);
+
+            // Confirmations of removed owners no longer count
+            let remove = multisig.submit(Action::RemoveOwner(accounts.charlie)).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert!(multisig.confirm(remove).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(multisig.confirm(remove).is_ok());
+            assert!(multisig.execute(remove).is_ok());
+            assert!(!multisig.owners().contains(&accounts.charlie));
+            assert_eq!(multisig.confirmation_count(remove), 2);
+
+            // The threshold cannot exceed the number of owners
+            let invalid = multisig.submit(Action::ChangeThreshold(4)).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(multisig.confirm(invalid).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert!(multisig.confirm(invalid).is_ok());
+            assert_eq!(multisig.execute(invalid), Err(MultisigError::InvalidThreshold));
+        }
+    }
+
+    // Cross-contract calls cannot be dispatched off-chain, so token proposals are covered here.
+    // Needs a running substrate-contracts-node: `cargo test --features e2e-tests`.
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+        use pidchat_psp22::PSP22Error;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        // Helper function to deploy the token and a 2-of-2 multisig owned by Alice and Bob,
+        // then fund the multisig with `treasury` tokens. Returns (token, multisig).
+        async fn setup(
+            client: &mut ink_e2e::Client<C, E>,
+            treasury: Balance,
+        ) -> (AccountId, AccountId) {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let token = client
+                .instantiate("pidchat_psp22", &ink_e2e::alice(), PidChatPSP22Ref::new(1_000_000, None, None, 18), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let constructor = MultisigRef::new(ink::prelude::vec![alice, bob], 2, token);
+            let multisig = client
+                .instantiate("pidchat_multisig", &ink_e2e::alice(), constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let transfer = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.transfer(multisig, treasury));
+            client.call(&ink_e2e::alice(), transfer, 0, None).await.expect("transfer failed");
+            (token, multisig)
+        }
+
+        // Helper function to submit `action` as Alice and confirm it as Bob. Returns the id.
+        async fn submit_confirmed(client: &mut ink_e2e::Client<C, E>, multisig: AccountId, action: Action) -> u64 {
+            let submit = build_message::<MultisigRef>(multisig).call(|contract| contract.submit(action.clone()));
+            let id = client
+                .call(&ink_e2e::alice(), submit, 0, None)
+                .await
+                .expect("submit failed")
+                .return_value()
+                .unwrap();
+            let confirm = build_message::<MultisigRef>(multisig).call(|contract| contract.confirm(id));
+            client.call(&ink_e2e::bob(), confirm, 0, None).await.expect("confirm failed");
+            id
+        }
+
+        // Test executed Transfer and Approve proposals reach the token
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_token_proposals(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let (token, multisig) = setup(&mut client, 10_000).await;
+
+            let id = submit_confirmed(&mut client, multisig, Action::Transfer { to: charlie, value: 500 }).await;
+            let execute = build_message::<MultisigRef>(multisig).call(|contract| contract.execute(id));
+            client.call(&ink_e2e::alice(), execute, 0, None).await.expect("execute failed");
+
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(charlie));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 500);
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(multisig));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 9_500);
+            let proposal = build_message::<MultisigRef>(multisig).call(|contract| contract.get_proposal(id));
+            assert!(client.call_dry_run(&ink_e2e::alice(), &proposal, 0, None).await.return_value().unwrap().executed);
+
+            let id = submit_confirmed(&mut client, multisig, Action::Approve { spender: charlie, value: 700 }).await;
+            let execute = build_message::<MultisigRef>(multisig).call(|contract| contract.execute(id));
+            client.call(&ink_e2e::alice(), execute, 0, None).await.expect("execute failed");
+
+            let allowance =
+                build_message::<PidChatPSP22Ref>(token).call(|contract| contract.allowance(multisig, charlie));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &allowance, 0, None).await.return_value(), 700);
+            Ok(())
+        }
+
+        // Test a failed token call reverts the execution, leaving the proposal pending
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_failed_transfer_rolls_back(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let charlie = ink_e2e::account_id(ink_e2e::AccountKeyring::Charlie);
+            let (token, multisig) = setup(&mut client, 100).await;
+
+            let id = submit_confirmed(&mut client, multisig, Action::Transfer { to: charlie, value: 500 }).await;
+            let execute = build_message::<MultisigRef>(multisig).call(|contract| contract.execute(id));
+            let result = client.call_dry_run(&ink_e2e::alice(), &execute, 0, None).await;
+            assert_eq!(
+                result.return_value(),
+                Err(MultisigError::Token(PSP22Error::InsufficientBalance))
+            );
+            assert!(client.call(&ink_e2e::alice(), execute, 0, None).await.is_err());
+
+            // `executed` was set before the token call and rolled back with it
+            let proposal = build_message::<MultisigRef>(multisig).call(|contract| contract.get_proposal(id));
+            assert!(!client.call_dry_run(&ink_e2e::alice(), &proposal, 0, None).await.return_value().unwrap().executed);
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(multisig));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 100);
+
+            // Once funded, the same proposal can still be executed
+            let transfer = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.transfer(multisig, 400));
+            client.call(&ink_e2e::alice(), transfer, 0, None).await.expect("transfer failed");
+            let execute = build_message::<MultisigRef>(multisig).call(|contract| contract.execute(id));
+            client.call(&ink_e2e::alice(), execute, 0, None).await.expect("execute failed");
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(charlie));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 500);
+            Ok(())
+        }
+    }
+}
@@ -1,531 +1,4501 @@
-#![cfg_attr(not(feature = "std"), no_std, no_main)]
-#![feature(min_specialization)]
-#![warn(clippy::arithmetic_side_effects)]
-use ink::env::Environment;
-type DefaultAccountId = <ink::env::DefaultEnvironment as Environment>::AccountId;
-type DefaultBalance = <ink::env::DefaultEnvironment as Environment>::Balance;
-
-pub mod psp22 {
-    use ink::prelude::vec::Vec;
-    use crate::{DefaultAccountId, DefaultBalance,PSP22Error};
-
-    #[ink::trait_definition]
-    pub trait Psp22 {
-        #[ink(message)]
-        fn token_name(&self) -> Vec<u8>;
-
-        #[ink(message)]
-        fn token_symbol(&self) -> Vec<u8>;
-
-        #[ink(message)]
-        fn token_decimals(&self) -> u8;
-
-        #[ink(message)]
-        fn total_supply(&self) -> DefaultBalance;
-
-        #[ink(message)]
-        fn balance_of(&self, owner: DefaultAccountId) -> DefaultBalance;
-
-        #[ink(message)]
-        fn allowance(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> DefaultBalance;
-
-        #[ink(message)]
-        fn transfer(&mut self, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error>;
-
-        #[ink(message)]
-        fn transfer_from(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
-
-        #[ink(message)]
-        fn approve(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
-
-        #[ink(message)]
-        fn increase_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
-
-        #[ink(message)]
-        fn decrease_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;       
-
-        #[ink(message)]
-        fn burn(&mut self, from: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
-
-        #[ink(message)]
-        fn history(&self, page: u32, limit: u32) -> Vec<(DefaultAccountId, DefaultAccountId, DefaultBalance, u64)>;
-    }
-}
-#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
-#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-pub enum PSP22Error {
-    TransferFailed,
-    TransferFromFailed,
-    ApproveFailed,
-    IncreaseAllowanceFailed,
-    DecreaseAllowanceFailed,
-    BalanceNoAllocated,
-    InsufficientBalance,
-    InsufficientAllowance,
-}
-impl PSP22Error {
-    pub fn from_error(error: PSP22Error) -> Self {
-        match error {
-            PSP22Error::TransferFailed => Self::TransferFailed,
-            PSP22Error::TransferFromFailed => Self::TransferFromFailed,
-            PSP22Error::ApproveFailed => Self::ApproveFailed,
-            PSP22Error::IncreaseAllowanceFailed => Self::IncreaseAllowanceFailed,
-            PSP22Error::DecreaseAllowanceFailed => Self::DecreaseAllowanceFailed,
-            PSP22Error::BalanceNoAllocated => Self::BalanceNoAllocated,
-            PSP22Error::InsufficientBalance => Self::InsufficientBalance,
-            PSP22Error::InsufficientAllowance => Self::InsufficientAllowance,
-        }
-    }
-}
-
-#[ink::contract]
-pub mod token {
-    
-    use super::{
-        psp22::Psp22,
-        DefaultAccountId,
-        DefaultBalance,
-        PSP22Error,
-    };
-    use ink::codegen::{
-        EmitEvent,
-        Env,
-    };
-    use ink_storage::Mapping;
-    use ink::prelude::vec::Vec;
-    use ink::prelude::string::String;
-    use ink::prelude::string::ToString;
-    #[ink(storage)]
-    #[derive(Default)]
-    pub struct PidChatPSP22 {
-        name: Option<String>,
-        symbol: Option<String>,
-        decimals: u8,
-        allowances: Mapping<(DefaultAccountId, DefaultAccountId), DefaultBalance>,
-        balances: Mapping<DefaultAccountId, DefaultBalance>,
-        total_supply: DefaultBalance,
-        transfers: Mapping<DefaultAccountId, Vec<(DefaultAccountId, DefaultAccountId, DefaultBalance,u64)>>,
-    }
-
-    // Define the Transfer event
-    #[ink(event)]
-    pub struct Transfer {
-        #[ink(topic)]
-        from: Option<AccountId>,
-        #[ink(topic)]
-        to: Option<AccountId>,
-        value: Balance,
-    }
-
-    // Define the Approval event
-    #[ink(event)]
-    pub struct Approval {
-        #[ink(topic)]
-        owner: AccountId,
-        #[ink(topic)]
-        spender: AccountId,
-        value: Balance,
-    }
-
-    impl  PidChatPSP22 {
-        #[ink(constructor)]
-        pub fn new(
-            total_supply: Balance,
-            name: Option<String>,
-            symbol: Option<String>,
-            decimals: u8,
-        ) -> Self {
-            let mut instance = Self::default();
-            instance.name = name.or_else(|| Some("PidChat".to_string()));
-            instance.symbol = symbol.or_else(|| Some("PID".to_string()));
-            instance.decimals = decimals;
-            instance.total_supply = total_supply;
-            instance.allowances = Mapping::new();
-            instance.balances = Mapping::new();
-            instance.balances.insert(&Self::env().caller(), &total_supply);
-            instance.transfers = Mapping::new();
-            instance
-        }
-         // Helper function to record transfers
-         fn record_transfer(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) {
-            let timestamp = Self::env().block_timestamp();
-            
-            // Record transfer in sender's history
-            let transfer_from = (from, to, value, timestamp);
-            let mut transfers_from = self.transfers.get(&from).unwrap_or_default();
-            // Remove the oldest transfer if the history is full
-            if transfers_from.len() >= 100 {
-                // Remove first transfer or oldest transfer
-                transfers_from.remove(0);
-            }
-            transfers_from.push(transfer_from);
-            self.transfers.insert(&from, &transfers_from);
-            
-            // Record transfer in recipient's history
-            let transfer_to = (from, to, value, timestamp);
-            let mut transfers_to = self.transfers.get(&to).unwrap_or_default();
-            // Remove the oldest transfer if the history is full
-            if transfers_to.len() >= 100 {
-                // Remove first transfer or oldest transfer
-                transfers_to.remove(0);
-            }
-            transfers_to.push(transfer_to);
-            self.transfers.insert(&to, &transfers_to);
-        }
-
-        // Helper function to update balances
-        fn update_balances(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
-            let from_balance = self.balances.get(&from).unwrap_or(0);
-            if from_balance < value {
-                return Err(PSP22Error::InsufficientBalance);
-            }
-            
-            self.balances.insert(&from, &(from_balance - value));
-            self.balances.insert(&to, &(self.balances.get(&to).unwrap_or(0) + value));
-            Ok(())
-        }
-        // Helper function to emit transfer events
-        fn _emit_transfer_event(
-            &self,
-            from: Option<AccountId>,
-            to: Option<AccountId>,
-            amount: Balance,
-        ) {
-            self.env().emit_event(Transfer {
-                from,
-                to,
-                value: amount,
-            });
-        }
-        // Helper function to emit approval events
-        fn _emit_approval_event(&self, owner: AccountId, spender: AccountId, amount: Balance) {
-            self.env().emit_event(Approval {
-                owner,
-                spender,
-                value: amount,
-            });
-        }
-       
-    }
-
-    impl Psp22 for PidChatPSP22 {
-        #[ink(message)]
-        fn token_name(&self) -> Vec<u8> {
-            self.name.clone().unwrap_or_default().into()
-        }
-
-        #[ink(message)]
-        fn token_symbol(&self) -> Vec<u8> {
-            self.symbol.clone().unwrap_or_default().into()
-        }
-
-        #[ink(message)]
-        fn token_decimals(&self) -> u8 {
-            self.decimals
-        }
-        #[ink(message)]
-        fn total_supply(&self) -> DefaultBalance {
-            self.total_supply.into()
-        }
-
-        #[ink(message)]
-        fn balance_of(&self, owner: DefaultAccountId) -> DefaultBalance {
-            self.balances.get(&owner).unwrap_or(0)
-        }
-
-        #[ink(message)]
-        fn allowance(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> DefaultBalance {
-            self.allowances.get(&(owner, spender)).unwrap_or(0)
-        }
-
-        #[ink(message)]
-        fn transfer(&mut self, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
-            let caller = Self::env().caller();
-            // Update balances using helper function
-            self.update_balances(caller, to, value)?;
-            // Record transfer using helper function
-            self.record_transfer(caller, to, value);
-            // Emit transfer event using helper function
-            self._emit_transfer_event(Some(caller), Some(to), value);
-            Ok(())
-        }
-
-        #[ink(message)]
-        fn transfer_from(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
-            let caller = Self::env().caller();
-            
-            // Check allowance
-            let allowance = self.allowances.get(&(from, caller)).unwrap_or(0);
-            if allowance < value {
-                return Err(PSP22Error::InsufficientAllowance);
-            }
-            
-            // Update balances using helper function
-            self.update_balances(from, to, value)?;
-            // Remove allowance after successful transfer
-            self.allowances.remove(&(from, caller));
-            // Record transfer using helper function
-            self.record_transfer(from, to, value);
-            // Emit transfer event using helper function
-            self._emit_transfer_event(Some(from), Some(to), value);
-            Ok(())
-             
-        }
-
-        #[ink(message)]
-        fn approve(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
-            self.allowances.insert(&(Self::env().caller(), spender), &value);
-            Ok(())
-        }
-
-        #[ink(message)]
-        fn increase_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
-            let caller = Self::env().caller();          
-            //update the allowance
-            self.allowances.insert(&(caller, spender), &(self.allowances.get(&(caller, spender)).unwrap_or(0) + value));
-            Ok(())
-        }
-
-        #[ink(message)]
-        fn decrease_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
-            let caller = Self::env().caller();
-            let allowance = self.allowances.get(&(caller, spender)).unwrap_or(0);
-            if allowance < value {
-                return Err(PSP22Error::InsufficientAllowance);
-            }
-            //update the allowance
-            self.allowances.insert(&(caller, spender), &(allowance - value));
-            Ok(())
-        }
-
-        #[ink(message)]
-        fn burn(&mut self, from: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
-            let caller = Self::env().caller();
-            let balance = self.balances.get(&caller).unwrap_or(0);
-            if balance < value {
-                return Err(PSP22Error::InsufficientBalance);
-            }
-            //update the balance
-            self.balances.insert(&caller, &(balance - value));
-            self.total_supply = self.total_supply - value;
-            Ok(())
-        }
-        #[ink(message)]
-        fn history(&self, page: u32, limit: u32) -> Vec<(DefaultAccountId, DefaultAccountId, DefaultBalance, u64)> {
-            let caller = self.env().caller();
-            let transfers = self.transfers.get(&caller).unwrap_or_default();
-            
-            // Validate pagination parameters
-            if page == 0 || limit == 0 {
-                return Vec::new();
-            }
-
-            // Calculate pagination indices with overflow protection
-            let start = ((page - 1) * limit) as usize;
-            if start >= transfers.len() {
-                return Vec::new();
-            }
-
-            let end = (start + limit as usize).min(transfers.len());
-            
-            // Return the requested slice of history
-            transfers[start..end].to_vec()
-        }
-        
-    }
-    #[cfg(test)]
-    mod tests {
-        use super::*;    
-
-        use ink::env::test::{default_accounts, set_caller}; 
-        use ink::env::DefaultEnvironment;
-        use crate::Environment;
-        type Balance = <DefaultEnvironment as Environment>::Balance;
-
-        // Helper function to setup test environment
-        fn setup() -> PidChatPSP22 {        
-            let total_supply: Balance = 1_000_000;
-            PidChatPSP22::new(
-                total_supply,
-                Some("TestToken".to_string()),
-                Some("TST".to_string()),
-                18,
-            )
-        }
-
-        // Test basic token information
-        #[ink::test]
-        fn test_token_info() {
-            let contract = setup();
-            
-            assert_eq!(contract.token_name(), "TestToken".as_bytes().to_vec());
-            assert_eq!(contract.token_symbol(), "TST".as_bytes().to_vec());
-            assert_eq!(contract.token_decimals(), 18);
-            assert_eq!(contract.total_supply(), 1_000_000);
-        }
-
-        // Test initial balance
-        #[ink::test]
-        fn test_initial_balance() {
-            let contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            assert_eq!(contract.balance_of(accounts.alice), 1_000_000);
-            assert_eq!(contract.balance_of(accounts.bob), 0);
-        }
-
-        // Test transfer
-        #[ink::test]
-        fn test_transfer() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Transfer 100 tokens from Alice to Bob
-            assert!(contract.transfer(accounts.bob, 100).is_ok());
-            
-            // Check balances after transfer
-            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
-            assert_eq!(contract.balance_of(accounts.bob), 100);
-        }
-
-        // Test transfer with insufficient balance
-        #[ink::test]
-        fn test_transfer_insufficient_balance() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Try to transfer more than available balance
-            let result = contract.transfer(accounts.bob, 2_000_000);
-            assert_eq!(result, Err(PSP22Error::InsufficientBalance));
-        }
-
-        // Test approve and allowance
-        #[ink::test]
-        fn test_approve_and_allowance() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Approve Bob to spend 500 tokens
-            assert!(contract.approve(accounts.bob, 500).is_ok());
-            
-            // Check allowance
-            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 500);
-        }
-
-        // Test transfer_from
-        #[ink::test]
-        fn test_transfer_from() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Alice approves Bob to spend 500 tokens
-            assert!(contract.approve(accounts.bob, 500).is_ok());
-            
-            // Bob transfers 300 tokens from Alice to Charlie
-            set_caller::<DefaultEnvironment>(accounts.bob);
-            assert!(contract.transfer_from(accounts.alice, accounts.charlie, 300).is_ok());
-            
-            // Check balances
-            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 300);
-            assert_eq!(contract.balance_of(accounts.charlie), 300);
-            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 0);
-        }
-
-        // Test increase/decrease allowance
-        #[ink::test]
-        fn test_allowance_modifications() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Initial approve
-            assert!(contract.approve(accounts.bob, 500).is_ok());
-            
-            // Increase allowance
-            assert!(contract.increase_allowance(accounts.bob, 200).is_ok());
-            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 700);
-            
-            // Decrease allowance
-            assert!(contract.decrease_allowance(accounts.bob, 300).is_ok());
-            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 400);
-        }
-
-        // Test burn
-        #[ink::test]
-        fn test_burn() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Burn 100 tokens
-            assert!(contract.burn(accounts.alice, 100).is_ok());
-            
-            // Check balance and total supply
-            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
-            assert_eq!(contract.total_supply(), 1_000_000 - 100);
-        }
-
-        // Test transfer history
-        #[ink::test]
-        fn test_transfer_history() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Make some transfers
-            assert!(contract.transfer(accounts.bob, 100).is_ok());
-            assert!(contract.transfer(accounts.charlie, 200).is_ok());
-            
-            // Check history for Alice
-            let history = contract.history(1, 10);
-            assert_eq!(history.len(), 2);
-            
-            // Verify first transfer details
-            let (from, to, value, _) = history[0];
-            assert_eq!(from, accounts.alice);
-            assert_eq!(to, accounts.bob);
-            assert_eq!(value, 100);
-        }
-
-        // Test history pagination
-        #[ink::test]
-        fn test_history_pagination() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            
-            // Make multiple transfers
-            for i in 0..5 {
-                assert!(contract.transfer(accounts.bob, 100 + i).is_ok());
-            }
-            
-            // Test first page
-            let page1 = contract.history(1, 2);
-            assert_eq!(page1.len(), 2);
-            
-            // Test second page
-            let page2 = contract.history(2, 2);
-            assert_eq!(page2.len(), 2);
-            
-            // Test last page
-            let page3 = contract.history(3, 2);
-            assert_eq!(page3.len(), 1);
-            
-            // Test invalid page
-            let invalid_page = contract.history(10, 2);
-            assert_eq!(invalid_page.len(), 0);
-        }
-
-        // Test maximum history size
-        #[ink::test]
-        fn test_max_history_size() {
-            let mut contract = setup();
-            let accounts = default_accounts::<DefaultEnvironment>();
-            set_caller::<DefaultEnvironment>(accounts.alice);
-            // Make more transfers than MAX_HISTORY_SIZE
-            for i in 0..200 {
-                assert!(contract.transfer(accounts.bob, 100 + i).is_ok());
-            }
-            
-            // Check that history is limited to MAX_HISTORY_SIZE
-            let history = contract.history(1, 100);
-            assert_eq!(history.len(), 100); // Assuming MAX_HISTORY_SIZE is 100
-        }
-    }
-}
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![feature(min_specialization)]
+#![warn(clippy::arithmetic_side_effects)]
+use ink::env::Environment;
+use ink::prelude::string::String;
+use ink::prelude::vec::Vec;
+type DefaultAccountId = <ink::env::DefaultEnvironment as Environment>::AccountId;
+type DefaultBalance = <ink::env::DefaultEnvironment as Environment>::Balance;
+
+pub mod psp22 {
+    use ink::prelude::vec::Vec;
+    use crate::{DefaultAccountId, DefaultBalance, HistoryRecord, PSP22Error};
+
+    #[ink::trait_definition]
+    pub trait Psp22 {
+        #[ink(message)]
+        fn token_name(&self) -> Vec<u8>;
+
+        #[ink(message)]
+        fn token_symbol(&self) -> Vec<u8>;
+
+        #[ink(message)]
+        fn token_decimals(&self) -> u8;
+
+        #[ink(message)]
+        fn total_supply(&self) -> DefaultBalance;
+
+        #[ink(message)]
+        fn balance_of(&self, owner: DefaultAccountId) -> DefaultBalance;
+
+        #[ink(message)]
+        fn allowance(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> DefaultBalance;
+
+        #[ink(message)]
+        fn transfer(&mut self, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error>;
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
+
+        #[ink(message)]
+        fn approve(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;       
+
+        #[ink(message)]
+        fn burn(&mut self, from: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error>;
+
+        #[ink(message)]
+        fn history(&self, page: u32, limit: u32) -> Vec<HistoryRecord>;
+    }
+}
+pub mod flashloan {
+    use ink::prelude::vec::Vec;
+    use crate::{DefaultAccountId, DefaultBalance, FlashBorrowerError};
+
+    /// Hook called by `PidChatPSP22::flashloan` on the receiver, which holds the minted
+    /// `amount` during the call and must approve the token contract for `amount + fee`.
+    #[ink::trait_definition]
+    pub trait FlashBorrower {
+        #[ink(message)]
+        fn on_flashloan(
+            &mut self,
+            initiator: DefaultAccountId,
+            amount: DefaultBalance,
+            fee: DefaultBalance,
+            data: Vec<u8>,
+        ) -> Result<(), FlashBorrowerError>;
+    }
+}
+
+/// Per-account history kept as a ring buffer of the last `MAX_HISTORY_SIZE` records, shared
+/// with the contracts that record movements like the token, e.g. wrapped LUNES and the LP
+/// shares of the AMM pair. Each contract stores the records and the number of records ever
+/// appended to each account in two mappings of its own.
+pub mod history {
+    use ink::prelude::vec::Vec;
+    use ink::storage::traits::StorageKey;
+    use ink_storage::Mapping;
+    use crate::{DefaultAccountId, HistoryRecord};
+
+    /// Maximum number of entries kept in each account's history
+    pub const MAX_HISTORY_SIZE: u32 = 100;
+
+    /// Appends a record to an account's history, overwriting the oldest one once full.
+    pub fn push<R: StorageKey, C: StorageKey>(
+        records: &mut Mapping<(DefaultAccountId, u32), HistoryRecord, R>,
+        counts: &mut Mapping<DefaultAccountId, u32, C>,
+        account: DefaultAccountId,
+        record: &HistoryRecord,
+    ) {
+        let count = counts.get(account).unwrap_or(0);
+        records.insert((account, count.checked_rem(MAX_HISTORY_SIZE).unwrap_or(0)), record);
+        counts.insert(account, &count.wrapping_add(1));
+    }
+
+    /// Reads an account's history, oldest first.
+    pub fn read<R: StorageKey, C: StorageKey>(
+        records: &Mapping<(DefaultAccountId, u32), HistoryRecord, R>,
+        counts: &Mapping<DefaultAccountId, u32, C>,
+        account: DefaultAccountId,
+    ) -> Vec<HistoryRecord> {
+        let count = counts.get(account).unwrap_or(0);
+        let len = count.min(MAX_HISTORY_SIZE);
+        let oldest = count.saturating_sub(len);
+        (oldest..count)
+            .filter_map(|index| records.get((account, index.checked_rem(MAX_HISTORY_SIZE).unwrap_or(0))))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum FlashBorrowerError {
+    FlashloanRejected,
+}
+
+/// Per-account history entry. `id` links to the global transfer log, `memo` is the memo of
+/// the transfer if any, so it outlives the entry's eviction from the log.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct HistoryRecord {
+    pub id: u64,
+    pub from: DefaultAccountId,
+    pub to: DefaultAccountId,
+    pub value: DefaultBalance,
+    pub timestamp: u64,
+    pub memo: Vec<u8>,
+}
+
+/// Allowance of a spender, expired once the block timestamp reaches `expires_at`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct Allowance {
+    pub amount: DefaultBalance,
+    pub expires_at: Option<u64>,
+}
+
+impl Allowance {
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now >= expires_at)
+    }
+}
+
+/// Entry of the global transfer log. `from` is `None` for mints and `to` is `None` for burns.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct TransferRecord {
+    pub id: u64,
+    pub from: Option<DefaultAccountId>,
+    pub to: Option<DefaultAccountId>,
+    pub value: DefaultBalance,
+    pub timestamp: u64,
+    pub memo: Vec<u8>,
+}
+
+/// Lifecycle of a payment request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum RequestStatus {
+    Pending,
+    Paid,
+    Cancelled,
+}
+
+/// Payment request (invoice) asking `payer` to send `amount` to `payee` before `expiry`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct PaymentRequest {
+    pub id: u64,
+    pub payee: DefaultAccountId,
+    pub payer: DefaultAccountId,
+    pub amount: DefaultBalance,
+    pub memo: Vec<u8>,
+    pub expiry: u64,
+    pub status: RequestStatus,
+}
+
+/// Lifecycle of an escrowed tip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum TipStatus {
+    Pending,
+    Accepted,
+    Reclaimed,
+}
+
+/// Tip held in escrow by the contract until `recipient` accepts it or `sender` reclaims it
+/// after `expiry`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Tip {
+    pub id: u64,
+    pub sender: DefaultAccountId,
+    pub recipient: DefaultAccountId,
+    pub amount: DefaultBalance,
+    pub memo: Vec<u8>,
+    pub expiry: u64,
+    pub status: TipStatus,
+}
+
+/// How a red envelope is split between its claimers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum SplitMode {
+    Equal,
+    Random,
+}
+
+/// Red envelope: `total` tokens held in escrow and split between up to `count` claimers that
+/// know the preimage of `secret_hash`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Envelope {
+    pub id: u64,
+    pub creator: DefaultAccountId,
+    pub total: DefaultBalance,
+    pub count: u32,
+    pub remaining_amount: DefaultBalance,
+    pub remaining_count: u32,
+    pub split_mode: SplitMode,
+    pub secret_hash: [u8; 32],
+    pub expiry: u64,
+}
+
+/// Subscription plan registered by a merchant: `amount` charged every `period` ms.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Plan {
+    pub id: u64,
+    pub merchant: DefaultAccountId,
+    pub amount: DefaultBalance,
+    pub period: u64,
+}
+
+/// Lifecycle of a subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum SubscriptionStatus {
+    Active,
+    Cancelled,
+    Lapsed,
+}
+
+/// Subscription of `subscriber` to a plan, collectable again from `next_payment_at`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Subscription {
+    pub id: u64,
+    pub plan_id: u64,
+    pub subscriber: DefaultAccountId,
+    pub next_payment_at: u64,
+    pub status: SubscriptionStatus,
+}
+
+/// Payment stream releasing `rate_per_ms` tokens to `recipient` every ms between `start` and
+/// `stop`, out of a `deposit` held in escrow.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Stream {
+    pub id: u64,
+    pub sender: DefaultAccountId,
+    pub recipient: DefaultAccountId,
+    pub deposit: DefaultBalance,
+    pub rate_per_ms: DefaultBalance,
+    pub start: u64,
+    pub stop: u64,
+    pub withdrawn: DefaultBalance,
+    pub cancelled: bool,
+}
+
+/// State of the staking pool. Rewards accrue at `reward_rate` tokens per second, shared
+/// between stakers through `reward_per_token`, scaled by `REWARD_PRECISION`. `reward_pool`
+/// holds the funded rewards not distributed yet; accrual stops when it runs out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct StakingPool {
+    pub total_staked: DefaultBalance,
+    pub reward_rate: DefaultBalance,
+    pub reward_per_token: DefaultBalance,
+    pub last_update: u64,
+    pub reward_pool: DefaultBalance,
+    pub unbonding_period: u64,
+}
+
+/// Stake of an account. `rewards` were accrued up to `reward_per_token_paid`; `unbonding`
+/// has left the stake and can be withdrawn from `unbonding_release`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Stake {
+    pub amount: DefaultBalance,
+    pub reward_per_token_paid: DefaultBalance,
+    pub rewards: DefaultBalance,
+    pub unbonding: DefaultBalance,
+    pub unbonding_release: u64,
+}
+
+/// Key of a hold: held account, manager that placed it and reason.
+pub type HoldKey = (DefaultAccountId, DefaultAccountId, u32);
+
+/// Hash of an HTLC secret, tagged with the function used to compute it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum Hashlock {
+    Sha2x256([u8; 32]),
+    Keccak256([u8; 32]),
+}
+
+/// Lifecycle of a hash time-locked transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum HtlcStatus {
+    Locked,
+    Redeemed,
+    Refunded,
+}
+
+/// Hash time-locked transfer: `amount` is paid to `recipient` against the preimage of
+/// `hashlock` before `timelock`, or refunded to `sender` afterwards.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Htlc {
+    pub id: u64,
+    pub sender: DefaultAccountId,
+    pub recipient: DefaultAccountId,
+    pub amount: DefaultBalance,
+    pub hashlock: Hashlock,
+    pub timelock: u64,
+    pub status: HtlcStatus,
+}
+
+/// Token metadata shown by wallets, returned in one call by `metadata`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub uri: String,
+    pub description: String,
+}
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PSP22Error {
+    TransferFailed,
+    TransferFromFailed,
+    ApproveFailed,
+    IncreaseAllowanceFailed,
+    DecreaseAllowanceFailed,
+    BalanceNoAllocated,
+    InsufficientBalance,
+    InsufficientAllowance,
+    MemoTooLong,
+    RequestNotFound,
+    RequestNotPending,
+    RequestExpired,
+    InvalidExpiry,
+    WrongPayer,
+    Unauthorized,
+    TipNotFound,
+    TipNotPending,
+    TipNotExpired,
+    InvalidEnvelope,
+    EnvelopeNotFound,
+    EnvelopeEmpty,
+    EnvelopeExpired,
+    EnvelopeNotExpired,
+    AlreadyClaimed,
+    InvalidSecret,
+    InvalidPlan,
+    PlanNotFound,
+    SubscriptionNotFound,
+    SubscriptionNotActive,
+    PaymentNotDue,
+    InvalidStream,
+    StreamNotFound,
+    StreamNotActive,
+    HtlcNotFound,
+    HtlcNotLocked,
+    HtlcExpired,
+    HtlcNotExpired,
+    InvalidPreimage,
+    NotOwner,
+    Overflow,
+    UpgradeFailed,
+    AlreadyMigrated,
+    MetadataTooLong,
+    AllowanceExpired,
+    AllowanceChanged,
+    AllowanceNotZero,
+    FlashloanFailed,
+    FlashloanTooLarge,
+    InvalidFlashFee,
+    InsufficientStake,
+    UnbondingNotDue,
+    InsufficientHold,
+    RescueFailed,
+    BatchTooLarge,
+}
+impl PSP22Error {
+    pub fn from_error(error: PSP22Error) -> Self {
+        match error {
+            PSP22Error::TransferFailed => Self::TransferFailed,
+            PSP22Error::TransferFromFailed => Self::TransferFromFailed,
+            PSP22Error::ApproveFailed => Self::ApproveFailed,
+            PSP22Error::IncreaseAllowanceFailed => Self::IncreaseAllowanceFailed,
+            PSP22Error::DecreaseAllowanceFailed => Self::DecreaseAllowanceFailed,
+            PSP22Error::BalanceNoAllocated => Self::BalanceNoAllocated,
+            PSP22Error::InsufficientBalance => Self::InsufficientBalance,
+            PSP22Error::InsufficientAllowance => Self::InsufficientAllowance,
+            PSP22Error::MemoTooLong => Self::MemoTooLong,
+            PSP22Error::RequestNotFound => Self::RequestNotFound,
+            PSP22Error::RequestNotPending => Self::RequestNotPending,
+            PSP22Error::RequestExpired => Self::RequestExpired,
+            PSP22Error::InvalidExpiry => Self::InvalidExpiry,
+            PSP22Error::WrongPayer => Self::WrongPayer,
+            PSP22Error::Unauthorized => Self::Unauthorized,
+            PSP22Error::TipNotFound => Self::TipNotFound,
+            PSP22Error::TipNotPending => Self::TipNotPending,
+            PSP22Error::TipNotExpired => Self::TipNotExpired,
+            PSP22Error::InvalidEnvelope => Self::InvalidEnvelope,
+            PSP22Error::EnvelopeNotFound => Self::EnvelopeNotFound,
+            PSP22Error::EnvelopeEmpty => Self::EnvelopeEmpty,
+            PSP22Error::EnvelopeExpired => Self::EnvelopeExpired,
+            PSP22Error::EnvelopeNotExpired => Self::EnvelopeNotExpired,
+            PSP22Error::AlreadyClaimed => Self::AlreadyClaimed,
+            PSP22Error::InvalidSecret => Self::InvalidSecret,
+            PSP22Error::InvalidPlan => Self::InvalidPlan,
+            PSP22Error::PlanNotFound => Self::PlanNotFound,
+            PSP22Error::SubscriptionNotFound => Self::SubscriptionNotFound,
+            PSP22Error::SubscriptionNotActive => Self::SubscriptionNotActive,
+            PSP22Error::PaymentNotDue => Self::PaymentNotDue,
+            PSP22Error::InvalidStream => Self::InvalidStream,
+            PSP22Error::StreamNotFound => Self::StreamNotFound,
+            PSP22Error::StreamNotActive => Self::StreamNotActive,
+            PSP22Error::HtlcNotFound => Self::HtlcNotFound,
+            PSP22Error::HtlcNotLocked => Self::HtlcNotLocked,
+            PSP22Error::HtlcExpired => Self::HtlcExpired,
+            PSP22Error::HtlcNotExpired => Self::HtlcNotExpired,
+            PSP22Error::InvalidPreimage => Self::InvalidPreimage,
+            PSP22Error::NotOwner => Self::NotOwner,
+            PSP22Error::Overflow => Self::Overflow,
+            PSP22Error::UpgradeFailed => Self::UpgradeFailed,
+            PSP22Error::AlreadyMigrated => Self::AlreadyMigrated,
+            PSP22Error::MetadataTooLong => Self::MetadataTooLong,
+            PSP22Error::AllowanceExpired => Self::AllowanceExpired,
+            PSP22Error::AllowanceChanged => Self::AllowanceChanged,
+            PSP22Error::AllowanceNotZero => Self::AllowanceNotZero,
+            PSP22Error::FlashloanFailed => Self::FlashloanFailed,
+            PSP22Error::FlashloanTooLarge => Self::FlashloanTooLarge,
+            PSP22Error::InvalidFlashFee => Self::InvalidFlashFee,
+            PSP22Error::InsufficientStake => Self::InsufficientStake,
+            PSP22Error::UnbondingNotDue => Self::UnbondingNotDue,
+            PSP22Error::InsufficientHold => Self::InsufficientHold,
+            PSP22Error::RescueFailed => Self::RescueFailed,
+            PSP22Error::BatchTooLarge => Self::BatchTooLarge,
+        }
+    }
+}
+
+#[ink::contract]
+pub mod token {
+    
+    use super::{
+        flashloan::FlashBorrower,
+        history,
+        psp22::Psp22,
+        DefaultAccountId,
+        DefaultBalance,
+        Allowance,
+        Envelope,
+        Hashlock,
+        HistoryRecord,
+        HoldKey,
+        Htlc,
+        HtlcStatus,
+        PaymentRequest,
+        Plan,
+        PSP22Error,
+        RequestStatus,
+        SplitMode,
+        Stake,
+        StakingPool,
+        Stream,
+        Subscription,
+        SubscriptionStatus,
+        Tip,
+        TipStatus,
+        TokenMetadata,
+        TransferRecord,
+    };
+    use ink_storage::{
+        Lazy,
+        Mapping,
+    };
+    use ink::storage::traits::{
+        ManualKey,
+        StorageKey,
+    };
+    use ink::codegen::TraitCallBuilder;
+    use ink::env::call::{
+        build_call,
+        ExecutionInput,
+        Selector,
+    };
+    use ink::env::CallFlags;
+    use ink::primitives::KeyComposer;
+    use ink::env::hash::{
+        Blake2x256,
+        Keccak256,
+        Sha2x256,
+    };
+    use ink::prelude::vec::Vec;
+    use ink::prelude::string::String;
+    use ink::prelude::string::ToString;
+
+    // Current storage layout version, see `migrate`. The first release has no version
+    // cell and is version 1.
+    const STORAGE_VERSION: u32 = 2;
+    // Maximum number of entries kept in the global transfer log
+    const MAX_TRANSFER_LOG_SIZE: u64 = 10_000;
+    // Maximum number of entries returned by a single `transfers_since` call
+    const MAX_TRANSFER_LOG_PAGE: u32 = 100;
+    // Maximum number of holders returned by a single `holders` call
+    const MAX_HOLDERS_PAGE: u32 = 100;
+    // Maximum number of entries converted by a single migration batch
+    const MAX_MIGRATION_BATCH: usize = 100;
+    // Maximum number of spenders returned by a single `spenders_of` call or revoked by a
+    // single `revoke_all_allowances` call
+    const MAX_SPENDERS_PAGE: u32 = 100;
+    // Denominator of basis point fees
+    const BPS_DENOMINATOR: u16 = 10_000;
+    // Maximum length in bytes of a transfer memo
+    const MAX_MEMO_LENGTH: usize = 256;
+    // Maximum length in bytes of each metadata field
+    const MAX_METADATA_LENGTH: usize = 1_024;
+    // Maximum number of payment requests indexed per account
+    const MAX_REQUESTS_PER_ACCOUNT: usize = 100;
+    // Maximum number of tips indexed per account
+    const MAX_TIPS_PER_ACCOUNT: usize = 100;
+    // Maximum number of plans or subscriptions indexed per account
+    const MAX_SUBSCRIPTIONS_PER_ACCOUNT: usize = 100;
+    // Maximum number of subscriptions indexed per plan
+    const MAX_SUBSCRIPTIONS_PER_PLAN: usize = 1_000;
+    // Maximum number of streams indexed per account
+    const MAX_STREAMS_PER_ACCOUNT: usize = 100;
+    // Scale of the staking reward per staked token
+    const REWARD_PRECISION: Balance = 1_000_000_000_000;
+
+    // Storage key map. Each `Lazy` and `Mapping` field lives under its own fixed key, so
+    // its data does not depend on field names or order. Keys are never reused; new fields
+    // get new keys and the root cell is not extended.
+    //
+    // 0x01-0x0F: token metadata and supply
+    const NAME_KEY: u32 = 0x01;
+    const SYMBOL_KEY: u32 = 0x02;
+    const DECIMALS_KEY: u32 = 0x03;
+    const TOTAL_SUPPLY_KEY: u32 = 0x04;
+    const METADATA_URI_KEY: u32 = 0x05;
+    const DESCRIPTION_KEY: u32 = 0x06;
+    const HOLDER_COUNT_KEY: u32 = 0x07;
+    const FLASH_FEE_KEY: u32 = 0x08;
+    const STAKING_POOL_KEY: u32 = 0x09;
+    // 0x13 and up: mappings
+    const HISTORY_KEY: u32 = 0x13;
+    const HISTORY_COUNT_KEY: u32 = 0x14;
+    const TRANSFER_LOG_KEY: u32 = 0x15;
+    const PAYMENT_REQUESTS_KEY: u32 = 0x16;
+    const ACCOUNT_REQUESTS_KEY: u32 = 0x17;
+    const TIPS_KEY: u32 = 0x18;
+    const ACCOUNT_TIPS_KEY: u32 = 0x19;
+    const PENDING_TIPS_IN_KEY: u32 = 0x1A;
+    const PENDING_TIPS_OUT_KEY: u32 = 0x1B;
+    const ENVELOPES_KEY: u32 = 0x1C;
+    const ENVELOPE_CLAIMS_KEY: u32 = 0x1D;
+    const PLANS_KEY: u32 = 0x1E;
+    const MERCHANT_PLANS_KEY: u32 = 0x1F;
+    const SUBSCRIPTIONS_KEY: u32 = 0x20;
+    const PLAN_SUBSCRIPTIONS_KEY: u32 = 0x21;
+    const SUBSCRIBER_SUBSCRIPTIONS_KEY: u32 = 0x22;
+    const STREAMS_KEY: u32 = 0x23;
+    const ACCOUNT_STREAMS_KEY: u32 = 0x24;
+    const HTLCS_KEY: u32 = 0x25;
+    const HOLDERS_KEY: u32 = 0x26;
+    const HOLDER_INDEX_KEY: u32 = 0x27;
+    const SPENDERS_KEY: u32 = 0x28;
+    const SPENDER_INDEX_KEY: u32 = 0x29;
+    const SPENDER_COUNT_KEY: u32 = 0x2A;
+    const ALLOWANCE_EXPIRIES_KEY: u32 = 0x2B;
+    const STRICT_APPROVALS_KEY: u32 = 0x2C;
+    const STAKES_KEY: u32 = 0x2D;
+    const HELD_KEY: u32 = 0x2E;
+    const HOLDS_KEY: u32 = 0x2F;
+    const HOLD_MANAGERS_KEY: u32 = 0x30;
+    // 0x31 and up: storage version, ownership, escrow and id counters
+    const STORAGE_VERSION_KEY: u32 = 0x31;
+    const OWNER_KEY: u32 = 0x32;
+    const PENDING_OWNER_KEY: u32 = 0x33;
+    const ESCROWED_KEY: u32 = 0x34;
+    const LAST_TRANSFER_ID_KEY: u32 = 0x35;
+    const LAST_REQUEST_ID_KEY: u32 = 0x36;
+    const LAST_TIP_ID_KEY: u32 = 0x37;
+    const LAST_ENVELOPE_ID_KEY: u32 = 0x38;
+    const LAST_PLAN_ID_KEY: u32 = 0x39;
+    const LAST_SUBSCRIPTION_ID_KEY: u32 = 0x3A;
+    const LAST_STREAM_ID_KEY: u32 = 0x3B;
+    const LAST_HTLC_ID_KEY: u32 = 0x3C;
+    // Keys ink derived from the field names of the first release. Balances and allowance
+    // amounts keep theirs, so their entries need no conversion.
+    const BALANCES_KEY: u32 = KeyComposer::from_str("PidChatPSP22::balances");
+    const ALLOWANCES_KEY: u32 = KeyComposer::from_str("PidChatPSP22::allowances");
+    const LEGACY_TRANSFERS_KEY: u32 = KeyComposer::from_str("PidChatPSP22::transfers");
+
+    // History entry of the first release: sender, recipient, value and timestamp
+    type LegacyTransfer = (DefaultAccountId, DefaultAccountId, DefaultBalance, u64);
+
+    /// Root cell layout of the first release, which kept metadata and total supply there.
+    /// `migrate` moves them to their own cells, after which it stays empty.
+    #[derive(Debug, Default, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+    struct LegacyRoot {
+        name: Option<String>,
+        symbol: Option<String>,
+        decimals: u8,
+        total_supply: DefaultBalance,
+    }
+
+    /// Token storage. The root cell (key `0`) only holds `LegacyRoot`; everything else sits
+    /// under the fixed keys listed above. Messages such as `flashloan` call out to contracts
+    /// that may re-enter the token, and the root cell written back when the outer message
+    /// returns would undo their changes, so no live state is kept there.
+    #[ink(storage)]
+    #[derive(Default)]
+    pub struct PidChatPSP22 {
+        name: Lazy<String, ManualKey<NAME_KEY>>,
+        symbol: Lazy<String, ManualKey<SYMBOL_KEY>>,
+        decimals: Lazy<u8, ManualKey<DECIMALS_KEY>>,
+        // Only non-zero allowances are stored, with their expiry if they have one
+        allowances: Mapping<(DefaultAccountId, DefaultAccountId), DefaultBalance, ManualKey<ALLOWANCES_KEY>>,
+        allowance_expiries: Mapping<(DefaultAccountId, DefaultAccountId), u64, ManualKey<ALLOWANCE_EXPIRIES_KEY>>,
+        // Spenders with a non-zero allowance per owner, dense in `0..spender_count`
+        spenders: Mapping<(DefaultAccountId, u32), DefaultAccountId, ManualKey<SPENDERS_KEY>>,
+        spender_index: Mapping<(DefaultAccountId, DefaultAccountId), u32, ManualKey<SPENDER_INDEX_KEY>>,
+        spender_count: Mapping<DefaultAccountId, u32, ManualKey<SPENDER_COUNT_KEY>>,
+        // Owners whose allowances must be reset to zero before `approve` sets a new value
+        strict_approvals: Mapping<DefaultAccountId, (), ManualKey<STRICT_APPROVALS_KEY>>,
+        // Staked tokens and the reward pool are held in escrow
+        stakes: Mapping<DefaultAccountId, Stake, ManualKey<STAKES_KEY>>,
+        // Part of each balance that cannot be moved, in total and per manager and reason
+        held: Mapping<DefaultAccountId, DefaultBalance, ManualKey<HELD_KEY>>,
+        holds: Mapping<HoldKey, DefaultBalance, ManualKey<HOLDS_KEY>>,
+        // Accounts, typically module contracts, allowed to place and release holds
+        hold_managers: Mapping<DefaultAccountId, (), ManualKey<HOLD_MANAGERS_KEY>>,
+        // Only non-zero balances are stored
+        balances: Mapping<DefaultAccountId, DefaultBalance, ManualKey<BALANCES_KEY>>,
+        // Holders with a non-zero balance, `holders` is dense in `0..holder_count`
+        holders: Mapping<u32, DefaultAccountId, ManualKey<HOLDERS_KEY>>,
+        holder_index: Mapping<DefaultAccountId, u32, ManualKey<HOLDER_INDEX_KEY>>,
+        holder_count: Lazy<u32, ManualKey<HOLDER_COUNT_KEY>>,
+        // Flash loan fee in basis points of the borrowed amount
+        flash_fee_bps: Lazy<u16, ManualKey<FLASH_FEE_KEY>>,
+        staking_pool: Lazy<StakingPool, ManualKey<STAKING_POOL_KEY>>,
+        total_supply: Lazy<DefaultBalance, ManualKey<TOTAL_SUPPLY_KEY>>,
+        metadata_uri: Lazy<String, ManualKey<METADATA_URI_KEY>>,
+        description: Lazy<String, ManualKey<DESCRIPTION_KEY>>,
+        // Ring buffer of the last `history::MAX_HISTORY_SIZE` records of each account
+        history: Mapping<(DefaultAccountId, u32), HistoryRecord, ManualKey<HISTORY_KEY>>,
+        // Number of records ever appended to each account's history
+        history_count: Mapping<DefaultAccountId, u32, ManualKey<HISTORY_COUNT_KEY>>,
+        // Histories written by the first release, oldest first, see `migrate_accounts`
+        legacy_transfers: Mapping<DefaultAccountId, Vec<LegacyTransfer>, ManualKey<LEGACY_TRANSFERS_KEY>>,
+        transfer_log: Mapping<u64, TransferRecord, ManualKey<TRANSFER_LOG_KEY>>,
+        payment_requests: Mapping<u64, PaymentRequest, ManualKey<PAYMENT_REQUESTS_KEY>>,
+        account_requests: Mapping<DefaultAccountId, Vec<u64>, ManualKey<ACCOUNT_REQUESTS_KEY>>,
+        tips: Mapping<u64, Tip, ManualKey<TIPS_KEY>>,
+        account_tips: Mapping<DefaultAccountId, Vec<u64>, ManualKey<ACCOUNT_TIPS_KEY>>,
+        pending_tips_in: Mapping<DefaultAccountId, DefaultBalance, ManualKey<PENDING_TIPS_IN_KEY>>,
+        pending_tips_out: Mapping<DefaultAccountId, DefaultBalance, ManualKey<PENDING_TIPS_OUT_KEY>>,
+        envelopes: Mapping<u64, Envelope, ManualKey<ENVELOPES_KEY>>,
+        envelope_claims: Mapping<(u64, DefaultAccountId), DefaultBalance, ManualKey<ENVELOPE_CLAIMS_KEY>>,
+        plans: Mapping<u64, Plan, ManualKey<PLANS_KEY>>,
+        merchant_plans: Mapping<DefaultAccountId, Vec<u64>, ManualKey<MERCHANT_PLANS_KEY>>,
+        subscriptions: Mapping<u64, Subscription, ManualKey<SUBSCRIPTIONS_KEY>>,
+        plan_subscriptions: Mapping<u64, Vec<u64>, ManualKey<PLAN_SUBSCRIPTIONS_KEY>>,
+        subscriber_subscriptions: Mapping<DefaultAccountId, Vec<u64>, ManualKey<SUBSCRIBER_SUBSCRIPTIONS_KEY>>,
+        streams: Mapping<u64, Stream, ManualKey<STREAMS_KEY>>,
+        account_streams: Mapping<DefaultAccountId, Vec<u64>, ManualKey<ACCOUNT_STREAMS_KEY>>,
+        htlcs: Mapping<u64, Htlc, ManualKey<HTLCS_KEY>>,
+        legacy: LegacyRoot,
+        storage_version: Lazy<u32, ManualKey<STORAGE_VERSION_KEY>>,
+        // Account allowed to call admin messages, e.g. a timelock controller
+        owner: Lazy<Option<DefaultAccountId>, ManualKey<OWNER_KEY>>,
+        pending_owner: Lazy<Option<DefaultAccountId>, ManualKey<PENDING_OWNER_KEY>>,
+        // Total held by the contract account on behalf of escrow features
+        escrowed: Lazy<DefaultBalance, ManualKey<ESCROWED_KEY>>,
+        last_transfer_id: Lazy<u64, ManualKey<LAST_TRANSFER_ID_KEY>>,
+        last_request_id: Lazy<u64, ManualKey<LAST_REQUEST_ID_KEY>>,
+        last_tip_id: Lazy<u64, ManualKey<LAST_TIP_ID_KEY>>,
+        last_envelope_id: Lazy<u64, ManualKey<LAST_ENVELOPE_ID_KEY>>,
+        last_plan_id: Lazy<u64, ManualKey<LAST_PLAN_ID_KEY>>,
+        last_subscription_id: Lazy<u64, ManualKey<LAST_SUBSCRIPTION_ID_KEY>>,
+        last_stream_id: Lazy<u64, ManualKey<LAST_STREAM_ID_KEY>>,
+        last_htlc_id: Lazy<u64, ManualKey<LAST_HTLC_ID_KEY>>,
+    }
+
+    // Define the Transfer event
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+        id: u64,
+    }
+
+    // Define the TransferWithMemo event, indexed by the blake2x256 hash of the memo
+    #[ink(event)]
+    pub struct TransferWithMemo {
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        value: Balance,
+        id: u64,
+        #[ink(topic)]
+        memo_hash: [u8; 32],
+        memo: Vec<u8>,
+    }
+
+    // Define the RequestCreated event
+    #[ink(event)]
+    pub struct RequestCreated {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        payee: AccountId,
+        #[ink(topic)]
+        payer: AccountId,
+        amount: Balance,
+        expiry: u64,
+    }
+
+    // Define the RequestPaid event
+    #[ink(event)]
+    pub struct RequestPaid {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        payer: AccountId,
+        transfer_id: u64,
+    }
+
+    // Define the RequestCancelled event
+    #[ink(event)]
+    pub struct RequestCancelled {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        cancelled_by: AccountId,
+    }
+
+    // Define the TipSent event
+    #[ink(event)]
+    pub struct TipSent {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        sender: AccountId,
+        #[ink(topic)]
+        recipient: AccountId,
+        amount: Balance,
+        expiry: u64,
+    }
+
+    // Define the TipAccepted event
+    #[ink(event)]
+    pub struct TipAccepted {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        recipient: AccountId,
+    }
+
+    // Define the TipReclaimed event
+    #[ink(event)]
+    pub struct TipReclaimed {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        sender: AccountId,
+    }
+
+    // Define the EnvelopeCreated event
+    #[ink(event)]
+    pub struct EnvelopeCreated {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        creator: AccountId,
+        total: Balance,
+        count: u32,
+        split_mode: SplitMode,
+        expiry: u64,
+    }
+
+    // Define the EnvelopeClaimed event
+    #[ink(event)]
+    pub struct EnvelopeClaimed {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        claimer: AccountId,
+        amount: Balance,
+    }
+
+    // Define the EnvelopeRefunded event
+    #[ink(event)]
+    pub struct EnvelopeRefunded {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        creator: AccountId,
+        amount: Balance,
+    }
+
+    // Define the PlanRegistered event
+    #[ink(event)]
+    pub struct PlanRegistered {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        merchant: AccountId,
+        amount: Balance,
+        period: u64,
+    }
+
+    // Define the Subscribed event
+    #[ink(event)]
+    pub struct Subscribed {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        plan_id: u64,
+        #[ink(topic)]
+        subscriber: AccountId,
+    }
+
+    // Define the PaymentCollected event
+    #[ink(event)]
+    pub struct PaymentCollected {
+        #[ink(topic)]
+        subscription_id: u64,
+        amount: Balance,
+        transfer_id: u64,
+        next_payment_at: u64,
+    }
+
+    // Define the SubscriptionLapsed event
+    #[ink(event)]
+    pub struct SubscriptionLapsed {
+        #[ink(topic)]
+        subscription_id: u64,
+    }
+
+    // Define the SubscriptionCancelled event
+    #[ink(event)]
+    pub struct SubscriptionCancelled {
+        #[ink(topic)]
+        subscription_id: u64,
+        #[ink(topic)]
+        cancelled_by: AccountId,
+    }
+
+    // Define the StreamCreated event
+    #[ink(event)]
+    pub struct StreamCreated {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        sender: AccountId,
+        #[ink(topic)]
+        recipient: AccountId,
+        deposit: Balance,
+        rate_per_ms: Balance,
+        start: u64,
+        stop: u64,
+    }
+
+    // Define the StreamWithdrawn event
+    #[ink(event)]
+    pub struct StreamWithdrawn {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        recipient: AccountId,
+        amount: Balance,
+    }
+
+    // Define the StreamCancelled event, with the final split between both parties
+    #[ink(event)]
+    pub struct StreamCancelled {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        cancelled_by: AccountId,
+        recipient_amount: Balance,
+        sender_amount: Balance,
+    }
+
+    // Define the HtlcLocked event
+    #[ink(event)]
+    pub struct HtlcLocked {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        sender: AccountId,
+        #[ink(topic)]
+        recipient: AccountId,
+        amount: Balance,
+        hashlock: Hashlock,
+        timelock: u64,
+    }
+
+    // Define the HtlcRedeemed event, revealing the preimage for the counterparty chain
+    #[ink(event)]
+    pub struct HtlcRedeemed {
+        #[ink(topic)]
+        id: u64,
+        preimage: Vec<u8>,
+    }
+
+    // Define the HtlcRefunded event
+    #[ink(event)]
+    pub struct HtlcRefunded {
+        #[ink(topic)]
+        id: u64,
+    }
+
+    // Define the Staked event
+    #[ink(event)]
+    pub struct Staked {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    // Define the Unstaked event, `release` is when the amount can be withdrawn
+    #[ink(event)]
+    pub struct Unstaked {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+        release: u64,
+    }
+
+    // Define the Withdrawn event, emitted when unbonded tokens are paid out
+    #[ink(event)]
+    pub struct Withdrawn {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    // Define the RewardsClaimed event
+    #[ink(event)]
+    pub struct RewardsClaimed {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    // Define the RewardsFunded event
+    #[ink(event)]
+    pub struct RewardsFunded {
+        #[ink(topic)]
+        funder: AccountId,
+        amount: Balance,
+    }
+
+    // Define the RewardRateChanged event
+    #[ink(event)]
+    pub struct RewardRateChanged {
+        old_rate: Balance,
+        new_rate: Balance,
+    }
+
+    // Define the UnbondingPeriodChanged event
+    #[ink(event)]
+    pub struct UnbondingPeriodChanged {
+        old: u64,
+        new: u64,
+    }
+
+    // Define the Held event
+    #[ink(event)]
+    pub struct Held {
+        #[ink(topic)]
+        account: AccountId,
+        #[ink(topic)]
+        manager: AccountId,
+        reason: u32,
+        amount: Balance,
+    }
+
+    // Define the HoldReleased event
+    #[ink(event)]
+    pub struct HoldReleased {
+        #[ink(topic)]
+        account: AccountId,
+        #[ink(topic)]
+        manager: AccountId,
+        reason: u32,
+        amount: Balance,
+    }
+
+    // Define the HoldManagerUpdated event
+    #[ink(event)]
+    pub struct HoldManagerUpdated {
+        #[ink(topic)]
+        manager: AccountId,
+        allowed: bool,
+    }
+
+    // Define the TokensRescued event
+    #[ink(event)]
+    pub struct TokensRescued {
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    // Define the NativeRescued event
+    #[ink(event)]
+    pub struct NativeRescued {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    // Define the SelfBalanceRecovered event
+    #[ink(event)]
+    pub struct SelfBalanceRecovered {
+        #[ink(topic)]
+        to: AccountId,
+        amount: Balance,
+    }
+
+    // Define the OwnershipTransferred event, `new_owner` is `None` once renounced
+    #[ink(event)]
+    pub struct OwnershipTransferred {
+        #[ink(topic)]
+        previous_owner: Option<AccountId>,
+        #[ink(topic)]
+        new_owner: Option<AccountId>,
+    }
+
+    // Define the OwnershipTransferStarted event, emitted when `new_owner` becomes pending
+    #[ink(event)]
+    pub struct OwnershipTransferStarted {
+        #[ink(topic)]
+        previous_owner: Option<AccountId>,
+        #[ink(topic)]
+        new_owner: AccountId,
+    }
+
+    // Define the Upgraded event
+    #[ink(event)]
+    pub struct Upgraded {
+        #[ink(topic)]
+        code_hash: [u8; 32],
+    }
+
+    // Define the Migrated event
+    #[ink(event)]
+    pub struct Migrated {
+        from_version: u32,
+        to_version: u32,
+    }
+
+    // Define the MetadataUpdated event, carrying the metadata after the change
+    #[ink(event)]
+    pub struct MetadataUpdated {
+        metadata: TokenMetadata,
+    }
+
+    // Define the FlashLoan event
+    #[ink(event)]
+    pub struct FlashLoan {
+        #[ink(topic)]
+        receiver: AccountId,
+        #[ink(topic)]
+        initiator: AccountId,
+        amount: Balance,
+        fee: Balance,
+    }
+
+    // Define the FlashFeeChanged event
+    #[ink(event)]
+    pub struct FlashFeeChanged {
+        old_bps: u16,
+        new_bps: u16,
+    }
+
+    // Define the Approval event
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: Balance,
+    }
+
+    impl  PidChatPSP22 {
+        #[ink(constructor)]
+        pub fn new(
+            total_supply: Balance,
+            name: Option<String>,
+            symbol: Option<String>,
+            decimals: u8,
+        ) -> Self {
+            Self::for_owner(Self::env().caller(), total_supply, name, symbol, decimals)
+        }
+
+        /// Like `new`, with `owner` instead of the caller receiving the supply and the admin
+        /// role. Used when the token is deployed by another contract, e.g. a factory.
+        #[ink(constructor)]
+        pub fn for_owner(
+            owner: AccountId,
+            total_supply: Balance,
+            name: Option<String>,
+            symbol: Option<String>,
+            decimals: u8,
+        ) -> Self {
+            let mut instance = Self::default();
+            instance.storage_version.set(&STORAGE_VERSION);
+            instance.owner.set(&Some(owner));
+            instance.name.set(&name.unwrap_or_else(|| "PidChat".to_string()));
+            instance.symbol.set(&symbol.unwrap_or_else(|| "PID".to_string()));
+            instance.decimals.set(&decimals);
+            instance.total_supply.set(&total_supply);
+            instance.set_balance(owner, total_supply);
+            // The initial supply is the first entry of the transfer log
+            let id = instance.log_transfer(None, Some(owner), total_supply, Vec::new());
+            instance._emit_transfer_event(None, Some(owner), total_supply, id);
+            Self::env().emit_event(OwnershipTransferred {
+                previous_owner: None,
+                new_owner: Some(owner),
+            });
+            instance
+        }
+
+        #[ink(message)]
+        pub fn owner(&self) -> Option<AccountId> {
+            self.owner.get().flatten()
+        }
+
+        #[ink(message)]
+        pub fn pending_owner(&self) -> Option<AccountId> {
+            self.pending_owner.get().flatten()
+        }
+
+        /// Starts handing admin messages over to `new_owner`, e.g. a timelock controller so
+        /// that every admin call has to be announced ahead of time. The handover completes
+        /// when `new_owner` calls `accept_ownership`, so a wrong address cannot lock admin out.
+        #[ink(message)]
+        pub fn transfer_ownership(&mut self, new_owner: AccountId) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            self.pending_owner.set(&Some(new_owner));
+            self.env().emit_event(OwnershipTransferStarted {
+                previous_owner: self.owner(),
+                new_owner,
+            });
+            Ok(())
+        }
+
+        /// Completes an ownership transfer. Only callable by the pending owner.
+        #[ink(message)]
+        pub fn accept_ownership(&mut self) -> Result<(), PSP22Error> {
+            let caller = Self::env().caller();
+            if self.pending_owner() != Some(caller) {
+                return Err(PSP22Error::NotOwner);
+            }
+            let previous_owner = self.owner();
+            self.owner.set(&Some(caller));
+            self.pending_owner.set(&None);
+            self.env().emit_event(OwnershipTransferred {
+                previous_owner,
+                new_owner: Some(caller),
+            });
+            Ok(())
+        }
+
+        /// Gives up ownership, permanently disabling admin messages.
+        #[ink(message)]
+        pub fn renounce_ownership(&mut self) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            let previous_owner = self.owner();
+            self.owner.set(&None);
+            self.pending_owner.set(&None);
+            self.env().emit_event(OwnershipTransferred {
+                previous_owner,
+                new_owner: None,
+            });
+            Ok(())
+        }
+
+        /// Replaces the contract code, keeping its storage and address. Admin only. The new
+        /// code has to read the storage layout of `STORAGE_VERSION`.
+        #[ink(message)]
+        pub fn upgrade(&mut self, code_hash: [u8; 32]) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            ink::env::set_code_hash(&code_hash).map_err(|_| PSP22Error::UpgradeFailed)?;
+            self.env().emit_event(Upgraded { code_hash });
+            Ok(())
+        }
+
+        /// Migrates a deployment of the first release, which has no upgrade message and whose
+        /// code is replaced with the root-only `Contracts::set_code`. Moves the metadata and
+        /// total supply out of the root cell and makes `owner` the admin. Callable by anyone
+        /// while the storage is at version 1, so it has to be dispatched in the same batch as
+        /// `set_code`.
+        ///
+        /// Balances and allowances keep their storage keys. Mappings cannot be iterated
+        /// on-chain, so the admin then completes the holder and spender indexes and converts
+        /// the histories in batches, from keys collected off-chain out of past events, see
+        /// `migrate_accounts` and `migrate_allowances`.
+        #[ink(message)]
+        pub fn migrate(&mut self, owner: AccountId) -> Result<(), PSP22Error> {
+            let from_version = self.storage_version();
+            if from_version >= STORAGE_VERSION {
+                return Err(PSP22Error::AlreadyMigrated);
+            }
+            let legacy = core::mem::take(&mut self.legacy);
+            self.name.set(&legacy.name.unwrap_or_default());
+            self.symbol.set(&legacy.symbol.unwrap_or_default());
+            self.decimals.set(&legacy.decimals);
+            self.total_supply.set(&legacy.total_supply);
+            self.owner.set(&Some(owner));
+            self.storage_version.set(&STORAGE_VERSION);
+            self.env().emit_event(Migrated {
+                from_version,
+                to_version: STORAGE_VERSION,
+            });
+            self.env().emit_event(OwnershipTransferred {
+                previous_owner: None,
+                new_owner: Some(owner),
+            });
+            Ok(())
+        }
+
+        /// Indexes the spenders of the given `(owner, spender)` pairs, for allowances given
+        /// before `migrate`. Admin only. Pairs without an allowance or already indexed are
+        /// skipped.
+        #[ink(message)]
+        pub fn migrate_allowances(&mut self, pairs: Vec<(AccountId, AccountId)>) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            if pairs.len() > MAX_MIGRATION_BATCH {
+                return Err(PSP22Error::BatchTooLarge);
+            }
+            for (owner, spender) in pairs {
+                if self.allowances.contains((owner, spender)) {
+                    self.index_spender(owner, spender);
+                }
+            }
+            Ok(())
+        }
+
+        /// Converts the state of the given accounts written before `migrate`. Admin only.
+        /// Accounts with a non-zero balance are indexed as holders and zero balances left in
+        /// storage are removed. Histories are converted ahead of the entries recorded since,
+        /// with id 0 and no memo. Accounts already converted are skipped.
+        #[ink(message)]
+        pub fn migrate_accounts(&mut self, accounts: Vec<AccountId>) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            if accounts.len() > MAX_MIGRATION_BATCH {
+                return Err(PSP22Error::BatchTooLarge);
+            }
+            for account in accounts {
+                if let Some(balance) = self.balances.get(account) {
+                    self.set_balance(account, balance);
+                }
+                let Some(legacy) = self.legacy_transfers.take(account) else {
+                    continue;
+                };
+                let mut records: Vec<HistoryRecord> = legacy
+                    .into_iter()
+                    .map(|(from, to, value, timestamp)| HistoryRecord {
+                        id: 0,
+                        from,
+                        to,
+                        value,
+                        timestamp,
+                        memo: Vec::new(),
+                    })
+                    .collect();
+                records.extend(self.read_history(account));
+                // Rewrite the ring from its first slot, the oldest entries drop out
+                self.history_count.insert(account, &0);
+                for record in records {
+                    self.push_history(account, record);
+                }
+            }
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn storage_version(&self) -> u32 {
+            self.storage_version.get().unwrap_or(1)
+        }
+
+        /// Number of holders with a non-zero balance. On an upgraded deployment, only complete
+        /// once `migrate_accounts` indexed the holders of the first release.
+        #[ink(message)]
+        pub fn holder_count(&self) -> u32 {
+            self.holder_count.get().unwrap_or(0)
+        }
+
+        /// Returns up to `limit` holders with their balance, starting at index `offset`.
+        /// Indexes are not stable: removing a holder moves the last one into its place.
+        #[ink(message)]
+        pub fn holders(&self, offset: u32, limit: u32) -> Vec<(AccountId, Balance)> {
+            let end = offset.saturating_add(limit.min(MAX_HOLDERS_PAGE)).min(self.holder_count());
+            (offset..end)
+                .filter_map(|index| self.holders.get(index))
+                .map(|holder| (holder, self.balances.get(holder).unwrap_or(0)))
+                .collect()
+        }
+
+        /// Returns up to `limit` spenders approved by `owner` with their allowance, starting at
+        /// index `offset`. Indexes are not stable, as for `holders`. On an upgraded deployment,
+        /// allowances given before `migrate` are only listed once `migrate_allowances` indexed
+        /// them.
+        #[ink(message)]
+        pub fn spenders_of(&self, owner: AccountId, offset: u32, limit: u32) -> Vec<(AccountId, Balance)> {
+            let end = offset.saturating_add(limit.min(MAX_SPENDERS_PAGE)).min(self.spender_count.get(owner).unwrap_or(0));
+            (offset..end)
+                .filter_map(|index| self.spenders.get((owner, index)))
+                .map(|spender| (spender, self.allowance(owner, spender)))
+                .collect()
+        }
+
+        /// Zeroes up to `MAX_SPENDERS_PAGE` of the allowances listed by `spenders_of` for the
+        /// caller. Returns the number of spenders left,
+        /// call again until it is zero. Allowances not indexed by `migrate_allowances` yet are
+        /// not covered.
+        #[ink(message)]
+        pub fn revoke_all_allowances(&mut self) -> u32 {
+            let caller = self.env().caller();
+            let count = self.spender_count.get(caller).unwrap_or(0);
+            let remaining = count.saturating_sub(MAX_SPENDERS_PAGE);
+            // Remove from the end so that no spender is moved by the swap-remove
+            for index in (remaining..count).rev() {
+                if let Some(spender) = self.spenders.get((caller, index)) {
+                    self.set_allowance(caller, spender, 0, None);
+                }
+            }
+            remaining
+        }
+
+        /// Approves `spender` for `value` until the block timestamp reaches `expires_at`.
+        #[ink(message)]
+        pub fn approve_with_expiry(&mut self, spender: AccountId, value: Balance, expires_at: u64) -> Result<(), PSP22Error> {
+            if expires_at <= self.env().block_timestamp() {
+                return Err(PSP22Error::InvalidExpiry);
+            }
+            let caller = self.env().caller();
+            self.ensure_approve_allowed(caller, spender, value)?;
+            self.set_allowance(caller, spender, value, Some(expires_at));
+            Ok(())
+        }
+
+        /// Sets the allowance of `spender` to `new_value` only if it currently is
+        /// `expected_current`, so a spender cannot use both the old and the new allowance by
+        /// front-running the change. The expiry of the current allowance is kept.
+        #[ink(message)]
+        pub fn approve_if(&mut self, spender: AccountId, expected_current: Balance, new_value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let current = self.live_allowance(caller, spender);
+            if current.amount != expected_current {
+                return Err(PSP22Error::AllowanceChanged);
+            }
+            self.set_allowance(caller, spender, new_value, current.expires_at);
+            Ok(())
+        }
+
+        /// Enables or disables strict mode for the caller: `approve` and
+        /// `approve_with_expiry` then fail unless the allowance is reset to zero first.
+        #[ink(message)]
+        pub fn set_strict_approvals(&mut self, enabled: bool) {
+            let caller = self.env().caller();
+            if enabled {
+                self.strict_approvals.insert(caller, &());
+            } else {
+                self.strict_approvals.remove(caller);
+            }
+        }
+
+        #[ink(message)]
+        pub fn strict_approvals(&self, owner: AccountId) -> bool {
+            self.strict_approvals.contains(owner)
+        }
+
+        /// Returns the allowance as stored, with its expiry. Unlike `allowance`, the amount of
+        /// an expired approval is returned as is.
+        #[ink(message)]
+        pub fn allowance_with_expiry(&self, owner: AccountId, spender: AccountId) -> Allowance {
+            self.allowance_entry(owner, spender)
+        }
+
+        /// Renames the token. Admin only.
+        #[ink(message)]
+        pub fn set_name(&mut self, name: String) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            Self::ensure_metadata_length(&name)?;
+            self.name.set(&name);
+            self.emit_metadata_updated();
+            Ok(())
+        }
+
+        /// Changes the token symbol. Admin only.
+        #[ink(message)]
+        pub fn set_symbol(&mut self, symbol: String) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            Self::ensure_metadata_length(&symbol)?;
+            self.symbol.set(&symbol);
+            self.emit_metadata_updated();
+            Ok(())
+        }
+
+        /// Sets the URI of the off-chain metadata, e.g. logo and website. Admin only.
+        #[ink(message)]
+        pub fn set_metadata_uri(&mut self, uri: String) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            Self::ensure_metadata_length(&uri)?;
+            self.metadata_uri.set(&uri);
+            self.emit_metadata_updated();
+            Ok(())
+        }
+
+        /// Sets the token description. Admin only.
+        #[ink(message)]
+        pub fn set_description(&mut self, description: String) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            Self::ensure_metadata_length(&description)?;
+            self.description.set(&description);
+            self.emit_metadata_updated();
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn token_uri(&self) -> String {
+            self.metadata_uri.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn metadata(&self) -> TokenMetadata {
+            TokenMetadata {
+                name: self.name.get().unwrap_or_default(),
+                symbol: self.symbol.get().unwrap_or_default(),
+                decimals: self.decimals.get().unwrap_or_default(),
+                uri: self.token_uri(),
+                description: self.description.get().unwrap_or_default(),
+            }
+        }
+
+        /// Mints `value` new tokens to `to`. Admin only.
+        #[ink(message)]
+        pub fn mint(&mut self, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            self.mint_to(to, value)
+        }
+
+        /// Sends `amount` of a foreign PSP22 `token` held by this contract to `to`. Admin only.
+        /// PID sent to the contract is recovered with `recover_self_balance` instead.
+        ///
+        /// The standard `PSP22::transfer(to, value, data)` is called first. Tokens that do not
+        /// dispatch its selector, such as other `PidChatPSP22` deployments, are called through
+        /// the `Psp22::transfer(to, value)` message of this crate instead.
+        #[ink(message)]
+        pub fn rescue_psp22(&mut self, token: AccountId, to: AccountId, amount: Balance) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            if token == self.env().account_id() {
+                return Err(PSP22Error::Unauthorized);
+            }
+            let standard = build_call::<Environment>()
+                .call(token)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(ink::selector_bytes!("PSP22::transfer")))
+                        .push_arg(to)
+                        .push_arg(amount)
+                        .push_arg(Vec::<u8>::new()),
+                )
+                // The standard error does not share the encoding of `PSP22Error`, only its
+                // variant index is read
+                .returns::<Result<(), ()>>()
+                .try_invoke();
+            match standard {
+                Ok(Ok(result)) => result.map_err(|_| PSP22Error::RescueFailed)?,
+                // The selector is unknown to the token
+                Ok(Err(ink::LangError::CouldNotReadInput)) => {
+                    let mut foreign: ink::contract_ref!(Psp22) = token.into();
+                    foreign
+                        .call_mut()
+                        .transfer(to, amount)
+                        .try_invoke()
+                        .map_err(|_| PSP22Error::RescueFailed)?
+                        .map_err(|_| PSP22Error::RescueFailed)??;
+                }
+                _ => return Err(PSP22Error::RescueFailed),
+            }
+            self.env().emit_event(TokensRescued { token, to, amount });
+            Ok(())
+        }
+
+        /// Sends `amount` of the native balance of this contract to `to`. Admin only.
+        #[ink(message)]
+        pub fn rescue_native(&mut self, to: AccountId, amount: Balance) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            self.env().transfer(to, amount).map_err(|_| PSP22Error::RescueFailed)?;
+            self.env().emit_event(NativeRescued { to, amount });
+            Ok(())
+        }
+
+        /// Sends the PID credited to this contract's own account to `to`, leaving the tokens
+        /// held in escrow by tips, envelopes, streams, staking and the like. Admin only.
+        /// Returns the recovered amount.
+        #[ink(message)]
+        pub fn recover_self_balance(&mut self, to: AccountId) -> Result<Balance, PSP22Error> {
+            self.ensure_owner()?;
+            let this = self.env().account_id();
+            let amount = self.spendable_balance_of(this).saturating_sub(self.escrowed());
+            if amount > 0 {
+                self.move_tokens(this, to, amount, Vec::new())?;
+            }
+            self.env().emit_event(SelfBalanceRecovered { to, amount });
+            Ok(amount)
+        }
+
+        /// Lends `amount` newly minted tokens to `receiver` for the duration of its
+        /// `FlashBorrower::on_flashloan` hook. The receiver must approve this contract for
+        /// `amount + fee` before returning; that much is then burned from its balance, and the
+        /// whole call reverts if it cannot be.
+        #[ink(message)]
+        pub fn flashloan(&mut self, receiver: AccountId, amount: Balance, data: Vec<u8>) -> Result<(), PSP22Error> {
+            if amount > self.max_flashloan() {
+                return Err(PSP22Error::FlashloanTooLarge);
+            }
+            let fee = self.flash_fee(amount);
+            let repayment = amount.checked_add(fee).ok_or(PSP22Error::Overflow)?;
+            let initiator = self.env().caller();
+            self.mint_to(receiver, amount)?;
+
+            // The borrower may re-enter the token, which is safe as no state is kept in the
+            // root cell, see `PidChatPSP22`
+            let mut borrower: ink::contract_ref!(FlashBorrower) = receiver.into();
+            borrower
+                .call_mut()
+                .on_flashloan(initiator, amount, fee, data)
+                .call_flags(CallFlags::default().set_allow_reentry(true))
+                .try_invoke()
+                .map_err(|_| PSP22Error::FlashloanFailed)?
+                .map_err(|_| PSP22Error::FlashloanFailed)?
+                .map_err(|_| PSP22Error::FlashloanFailed)?;
+            self.repay_flashloan(receiver, repayment)?;
+            self.env().emit_event(FlashLoan {
+                receiver,
+                initiator,
+                amount,
+                fee,
+            });
+            Ok(())
+        }
+
+        /// Largest amount that can be flash minted without overflowing the total supply.
+        #[ink(message)]
+        pub fn max_flashloan(&self) -> Balance {
+            Balance::MAX.saturating_sub(self.total_supply())
+        }
+
+        /// Fee charged on a flash loan of `amount`, rounded down.
+        #[ink(message)]
+        pub fn flash_fee(&self, amount: Balance) -> Balance {
+            let bps = Balance::from(self.flash_fee_bps());
+            // Split to avoid overflowing on large amounts
+            let whole = amount.checked_div(Balance::from(BPS_DENOMINATOR)).unwrap_or(0);
+            let rest = amount.checked_rem(Balance::from(BPS_DENOMINATOR)).unwrap_or(0);
+            whole
+                .saturating_mul(bps)
+                .saturating_add(rest.saturating_mul(bps).checked_div(Balance::from(BPS_DENOMINATOR)).unwrap_or(0))
+        }
+
+        #[ink(message)]
+        pub fn flash_fee_bps(&self) -> u16 {
+            self.flash_fee_bps.get().unwrap_or(0)
+        }
+
+        /// Sets the flash loan fee in basis points, at most 10_000. Admin only.
+        #[ink(message)]
+        pub fn set_flash_fee_bps(&mut self, new_bps: u16) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            if new_bps > BPS_DENOMINATOR {
+                return Err(PSP22Error::InvalidFlashFee);
+            }
+            let old_bps = self.flash_fee_bps();
+            self.flash_fee_bps.set(&new_bps);
+            self.env().emit_event(FlashFeeChanged { old_bps, new_bps });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn last_transfer_id(&self) -> u64 {
+            self.last_transfer_id.get().unwrap_or(0)
+        }
+
+        /// Returns up to `limit` log entries with an id greater than `id`, oldest first.
+        /// Entries evicted from the bounded log are skipped, so a gap between `id` and the
+        /// first returned id means the caller fell too far behind.
+        #[ink(message)]
+        pub fn transfers_since(&self, id: u64, limit: u32) -> Vec<TransferRecord> {
+            let last = self.last_transfer_id();
+            let oldest = last.saturating_sub(MAX_TRANSFER_LOG_SIZE).saturating_add(1);
+            let start = id.saturating_add(1).max(oldest);
+            let limit = u64::from(limit.min(MAX_TRANSFER_LOG_PAGE));
+            let end = start.saturating_add(limit).min(last.saturating_add(1));
+            (start..end).filter_map(|id| self.transfer_log.get(id)).collect()
+        }
+
+        /// Transfers `value` to `to` attaching a `memo` (e.g. a chat message or invoice reference).
+        /// The memo is stored in the transfer log entry and in the history of both accounts,
+        /// and emitted in a `TransferWithMemo` event indexed by its blake2x256 hash.
+        #[ink(message)]
+        pub fn transfer_with_memo(&mut self, to: AccountId, value: Balance, memo: Vec<u8>) -> Result<(), PSP22Error> {
+            if memo.len() > MAX_MEMO_LENGTH {
+                return Err(PSP22Error::MemoTooLong);
+            }
+            let caller = Self::env().caller();
+            let id = self.move_tokens(caller, to, value, memo.clone())?;
+            let memo_hash = self.env().hash_bytes::<Blake2x256>(&memo);
+            self.env().emit_event(TransferWithMemo {
+                from: caller,
+                to,
+                value,
+                id,
+                memo_hash,
+                memo,
+            });
+            Ok(())
+        }
+
+        /// Asks `payer` to send `amount` to the caller before `expiry` (block timestamp in ms).
+        /// Returns the id of the new request.
+        #[ink(message)]
+        pub fn create_request(&mut self, payer: AccountId, amount: Balance, memo: Vec<u8>, expiry: u64) -> Result<u64, PSP22Error> {
+            if memo.len() > MAX_MEMO_LENGTH {
+                return Err(PSP22Error::MemoTooLong);
+            }
+            if expiry <= Self::env().block_timestamp() {
+                return Err(PSP22Error::InvalidExpiry);
+            }
+            let payee = Self::env().caller();
+            let id = Self::next_id(&mut self.last_request_id);
+            self.payment_requests.insert(id, &PaymentRequest {
+                id,
+                payee,
+                payer,
+                amount,
+                memo,
+                expiry,
+                status: RequestStatus::Pending,
+            });
+            Self::index_id(&mut self.account_requests, payee, id, MAX_REQUESTS_PER_ACCOUNT);
+            if payer != payee {
+                Self::index_id(&mut self.account_requests, payer, id, MAX_REQUESTS_PER_ACCOUNT);
+            }
+            self.env().emit_event(RequestCreated {
+                id,
+                payee,
+                payer,
+                amount,
+                expiry,
+            });
+            Ok(id)
+        }
+
+        /// Pays a pending request. Only the requested payer can pay, exactly the requested
+        /// amount, and only before the request expires.
+        #[ink(message)]
+        pub fn pay_request(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut request = self.payment_requests.get(id).ok_or(PSP22Error::RequestNotFound)?;
+            if request.status != RequestStatus::Pending {
+                return Err(PSP22Error::RequestNotPending);
+            }
+            let caller = Self::env().caller();
+            if caller != request.payer {
+                return Err(PSP22Error::WrongPayer);
+            }
+            if Self::env().block_timestamp() > request.expiry {
+                return Err(PSP22Error::RequestExpired);
+            }
+            let transfer_id = self.move_tokens(caller, request.payee, request.amount, request.memo.clone())?;
+            request.status = RequestStatus::Paid;
+            self.payment_requests.insert(id, &request);
+            self.env().emit_event(RequestPaid {
+                id,
+                payer: caller,
+                transfer_id,
+            });
+            Ok(())
+        }
+
+        /// Cancels a pending request. The payee can withdraw it and the payer can decline it.
+        #[ink(message)]
+        pub fn cancel_request(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut request = self.payment_requests.get(id).ok_or(PSP22Error::RequestNotFound)?;
+            if request.status != RequestStatus::Pending {
+                return Err(PSP22Error::RequestNotPending);
+            }
+            let caller = Self::env().caller();
+            if caller != request.payee && caller != request.payer {
+                return Err(PSP22Error::Unauthorized);
+            }
+            request.status = RequestStatus::Cancelled;
+            self.payment_requests.insert(id, &request);
+            self.env().emit_event(RequestCancelled {
+                id,
+                cancelled_by: caller,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_request(&self, id: u64) -> Option<PaymentRequest> {
+            self.payment_requests.get(id)
+        }
+
+        /// Returns the most recent requests where `account` is either payee or payer.
+        #[ink(message)]
+        pub fn requests_for(&self, account: AccountId) -> Vec<PaymentRequest> {
+            self.account_requests
+                .get(account)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.payment_requests.get(id))
+                .collect()
+        }
+
+        /// Sends a tip to `to` through escrow. The tokens are held by the contract account (and
+        /// still count in `total_supply`) until `to` accepts the tip or the caller reclaims it
+        /// after `expiry`. Returns the id of the tip.
+        #[ink(message)]
+        pub fn send_tip(&mut self, to: AccountId, value: Balance, memo: Vec<u8>, expiry: u64) -> Result<u64, PSP22Error> {
+            if memo.len() > MAX_MEMO_LENGTH {
+                return Err(PSP22Error::MemoTooLong);
+            }
+            if expiry <= Self::env().block_timestamp() {
+                return Err(PSP22Error::InvalidExpiry);
+            }
+            let sender = Self::env().caller();
+            self.escrow_in(sender, value, memo.clone())?;
+            let id = Self::next_id(&mut self.last_tip_id);
+            self.tips.insert(id, &Tip {
+                id,
+                sender,
+                recipient: to,
+                amount: value,
+                memo,
+                expiry,
+                status: TipStatus::Pending,
+            });
+            self.pending_tips_out.insert(sender, &self.pending_tips_out.get(sender).unwrap_or(0).saturating_add(value));
+            self.pending_tips_in.insert(to, &self.pending_tips_in.get(to).unwrap_or(0).saturating_add(value));
+            Self::index_id(&mut self.account_tips, sender, id, MAX_TIPS_PER_ACCOUNT);
+            if to != sender {
+                Self::index_id(&mut self.account_tips, to, id, MAX_TIPS_PER_ACCOUNT);
+            }
+            self.env().emit_event(TipSent {
+                id,
+                sender,
+                recipient: to,
+                amount: value,
+                expiry,
+            });
+            Ok(id)
+        }
+
+        /// Accepts a pending tip, releasing the escrowed tokens to the recipient.
+        #[ink(message)]
+        pub fn accept_tip(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut tip = self.tips.get(id).ok_or(PSP22Error::TipNotFound)?;
+            if tip.status != TipStatus::Pending {
+                return Err(PSP22Error::TipNotPending);
+            }
+            if Self::env().caller() != tip.recipient {
+                return Err(PSP22Error::Unauthorized);
+            }
+            self.escrow_out(tip.recipient, tip.amount, tip.memo.clone())?;
+            self.settle_tip(&tip);
+            tip.status = TipStatus::Accepted;
+            self.tips.insert(id, &tip);
+            self.env().emit_event(TipAccepted {
+                id,
+                recipient: tip.recipient,
+            });
+            Ok(())
+        }
+
+        /// Returns an expired, unaccepted tip to its sender.
+        #[ink(message)]
+        pub fn reclaim_tip(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut tip = self.tips.get(id).ok_or(PSP22Error::TipNotFound)?;
+            if tip.status != TipStatus::Pending {
+                return Err(PSP22Error::TipNotPending);
+            }
+            if Self::env().caller() != tip.sender {
+                return Err(PSP22Error::Unauthorized);
+            }
+            if Self::env().block_timestamp() <= tip.expiry {
+                return Err(PSP22Error::TipNotExpired);
+            }
+            self.escrow_out(tip.sender, tip.amount, tip.memo.clone())?;
+            self.settle_tip(&tip);
+            tip.status = TipStatus::Reclaimed;
+            self.tips.insert(id, &tip);
+            self.env().emit_event(TipReclaimed {
+                id,
+                sender: tip.sender,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_tip(&self, id: u64) -> Option<Tip> {
+            self.tips.get(id)
+        }
+
+        /// Returns the most recent tips sent or received by `account`.
+        #[ink(message)]
+        pub fn tips_for(&self, account: AccountId) -> Vec<Tip> {
+            self.account_tips
+                .get(account)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.tips.get(id))
+                .collect()
+        }
+
+        /// Total of pending tips waiting for `account` to accept them.
+        #[ink(message)]
+        pub fn pending_incoming_tips(&self, account: AccountId) -> Balance {
+            self.pending_tips_in.get(account).unwrap_or(0)
+        }
+
+        /// Total of pending tips sent by `account` and still held in escrow.
+        #[ink(message)]
+        pub fn pending_outgoing_tips(&self, account: AccountId) -> Balance {
+            self.pending_tips_out.get(account).unwrap_or(0)
+        }
+
+        /// Drops a red envelope of `total` tokens for `count` claimers. The tokens are held in
+        /// escrow until claimed or refunded after `expiry`. `secret_hash` is the sha2-256 hash
+        /// of the secret shared in the chat. Returns the id of the envelope.
+        #[ink(message)]
+        pub fn create_envelope(
+            &mut self,
+            total: Balance,
+            count: u32,
+            split_mode: SplitMode,
+            secret_hash: [u8; 32],
+            expiry: u64,
+        ) -> Result<u64, PSP22Error> {
+            // Every claimer gets at least one unit
+            if count == 0 || total < Balance::from(count) {
+                return Err(PSP22Error::InvalidEnvelope);
+            }
+            if expiry <= Self::env().block_timestamp() {
+                return Err(PSP22Error::InvalidExpiry);
+            }
+            let creator = Self::env().caller();
+            self.escrow_in(creator, total, Vec::new())?;
+            let id = Self::next_id(&mut self.last_envelope_id);
+            self.envelopes.insert(id, &Envelope {
+                id,
+                creator,
+                total,
+                count,
+                remaining_amount: total,
+                remaining_count: count,
+                split_mode,
+                secret_hash,
+                expiry,
+            });
+            self.env().emit_event(EnvelopeCreated {
+                id,
+                creator,
+                total,
+                count,
+                split_mode,
+                expiry,
+            });
+            Ok(id)
+        }
+
+        /// Claims a share of an envelope by revealing its secret. Each account claims once,
+        /// first come first served.
+        ///
+        /// Random shares are derived from the block and the claimer and are predictable: a
+        /// claimer can compute their share beforehand and choose when to claim, and block
+        /// authors can influence it. Do not use random envelopes where this matters.
+        #[ink(message)]
+        pub fn claim_envelope(&mut self, id: u64, secret: Vec<u8>) -> Result<Balance, PSP22Error> {
+            let mut envelope = self.envelopes.get(id).ok_or(PSP22Error::EnvelopeNotFound)?;
+            let claimer = Self::env().caller();
+            if self.envelope_claims.contains((id, claimer)) {
+                return Err(PSP22Error::AlreadyClaimed);
+            }
+            if envelope.remaining_count == 0 {
+                return Err(PSP22Error::EnvelopeEmpty);
+            }
+            if Self::env().block_timestamp() > envelope.expiry {
+                return Err(PSP22Error::EnvelopeExpired);
+            }
+            if self.env().hash_bytes::<Sha2x256>(&secret) != envelope.secret_hash {
+                return Err(PSP22Error::InvalidSecret);
+            }
+            let amount = self.envelope_share(&envelope, claimer);
+            self.escrow_out(claimer, amount, Vec::new())?;
+            envelope.remaining_amount = envelope.remaining_amount.saturating_sub(amount);
+            envelope.remaining_count = envelope.remaining_count.saturating_sub(1);
+            self.envelopes.insert(id, &envelope);
+            self.envelope_claims.insert((id, claimer), &amount);
+            self.env().emit_event(EnvelopeClaimed {
+                id,
+                claimer,
+                amount,
+            });
+            Ok(amount)
+        }
+
+        /// Returns the unclaimed part of an expired envelope to its creator.
+        #[ink(message)]
+        pub fn refund_envelope(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut envelope = self.envelopes.get(id).ok_or(PSP22Error::EnvelopeNotFound)?;
+            if Self::env().caller() != envelope.creator {
+                return Err(PSP22Error::Unauthorized);
+            }
+            if Self::env().block_timestamp() <= envelope.expiry {
+                return Err(PSP22Error::EnvelopeNotExpired);
+            }
+            if envelope.remaining_amount == 0 {
+                return Err(PSP22Error::EnvelopeEmpty);
+            }
+            let amount = envelope.remaining_amount;
+            self.escrow_out(envelope.creator, amount, Vec::new())?;
+            envelope.remaining_amount = 0;
+            envelope.remaining_count = 0;
+            self.envelopes.insert(id, &envelope);
+            self.env().emit_event(EnvelopeRefunded {
+                id,
+                creator: envelope.creator,
+                amount,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_envelope(&self, id: u64) -> Option<Envelope> {
+            self.envelopes.get(id)
+        }
+
+        /// Amount claimed by `account` from an envelope, `None` if it has not claimed.
+        #[ink(message)]
+        pub fn envelope_claim_of(&self, id: u64, account: AccountId) -> Option<Balance> {
+            self.envelope_claims.get((id, account))
+        }
+
+        // Helper function to compute the share of the next claimer of an envelope.
+        // The last claimer takes what is left. Random shares are drawn in
+        // [1, 2 * average - 1] so every remaining claimer still gets at least one unit.
+        fn envelope_share(&self, envelope: &Envelope, claimer: AccountId) -> Balance {
+            let remaining_count = Balance::from(envelope.remaining_count);
+            if envelope.remaining_count <= 1 {
+                return envelope.remaining_amount;
+            }
+            let average = envelope.remaining_amount.checked_div(remaining_count).unwrap_or(0);
+            match envelope.split_mode {
+                SplitMode::Equal => average,
+                SplitMode::Random => {
+                    let seed = (
+                        Self::env().block_number(),
+                        Self::env().block_timestamp(),
+                        claimer,
+                        envelope.id,
+                        envelope.remaining_count,
+                    );
+                    let hash = self.env().hash_encoded::<Blake2x256, _>(&seed);
+                    let mut bytes = [0u8; 16];
+                    bytes.copy_from_slice(&hash[..16]);
+                    let max = average.saturating_mul(2).saturating_sub(1);
+                    u128::from_le_bytes(bytes).checked_rem(max).unwrap_or(0).saturating_add(1)
+                }
+            }
+        }
+
+        /// Registers a subscription plan charging `amount` to subscribers every `period` ms,
+        /// paid to the caller. Returns the id of the plan.
+        #[ink(message)]
+        pub fn register_plan(&mut self, amount: Balance, period: u64) -> Result<u64, PSP22Error> {
+            if amount == 0 || period == 0 {
+                return Err(PSP22Error::InvalidPlan);
+            }
+            let merchant = Self::env().caller();
+            let id = Self::next_id(&mut self.last_plan_id);
+            self.plans.insert(id, &Plan {
+                id,
+                merchant,
+                amount,
+                period,
+            });
+            Self::index_id(&mut self.merchant_plans, merchant, id, MAX_SUBSCRIPTIONS_PER_ACCOUNT);
+            self.env().emit_event(PlanRegistered {
+                id,
+                merchant,
+                amount,
+                period,
+            });
+            Ok(id)
+        }
+
+        /// Subscribes the caller to a plan. The first period is paid immediately, following
+        /// ones are pulled from the caller's balance by `collect`. Returns the subscription id.
+        #[ink(message)]
+        pub fn subscribe(&mut self, plan_id: u64) -> Result<u64, PSP22Error> {
+            let plan = self.plans.get(plan_id).ok_or(PSP22Error::PlanNotFound)?;
+            let subscriber = Self::env().caller();
+            self.move_tokens(subscriber, plan.merchant, plan.amount, Vec::new())?;
+            let id = Self::next_id(&mut self.last_subscription_id);
+            self.subscriptions.insert(id, &Subscription {
+                id,
+                plan_id,
+                subscriber,
+                next_payment_at: Self::env().block_timestamp().saturating_add(plan.period),
+                status: SubscriptionStatus::Active,
+            });
+            Self::index_id(&mut self.plan_subscriptions, plan_id, id, MAX_SUBSCRIPTIONS_PER_PLAN);
+            Self::index_id(&mut self.subscriber_subscriptions, subscriber, id, MAX_SUBSCRIPTIONS_PER_ACCOUNT);
+            self.env().emit_event(Subscribed {
+                id,
+                plan_id,
+                subscriber,
+            });
+            Ok(id)
+        }
+
+        /// Collects the current period of an active subscription once it is due. Anyone, e.g.
+        /// the merchant or a keeper, can call it since funds always go to the merchant. At
+        /// most one period is charged per call and missed periods are not charged back.
+        /// If the subscriber cannot pay, the subscription lapses instead of failing.
+        #[ink(message)]
+        pub fn collect(&mut self, subscription_id: u64) -> Result<(), PSP22Error> {
+            let mut subscription = self.subscriptions.get(subscription_id).ok_or(PSP22Error::SubscriptionNotFound)?;
+            if subscription.status != SubscriptionStatus::Active {
+                return Err(PSP22Error::SubscriptionNotActive);
+            }
+            let now = Self::env().block_timestamp();
+            if now < subscription.next_payment_at {
+                return Err(PSP22Error::PaymentNotDue);
+            }
+            let plan = self.plans.get(subscription.plan_id).ok_or(PSP22Error::PlanNotFound)?;
+            if self.spendable_balance_of(subscription.subscriber) < plan.amount {
+                subscription.status = SubscriptionStatus::Lapsed;
+                self.subscriptions.insert(subscription_id, &subscription);
+                self.env().emit_event(SubscriptionLapsed { subscription_id });
+                return Ok(());
+            }
+            let transfer_id = self.move_tokens(subscription.subscriber, plan.merchant, plan.amount, Vec::new())?;
+            let mut next_payment_at = subscription.next_payment_at.saturating_add(plan.period);
+            if next_payment_at <= now {
+                next_payment_at = now.saturating_add(plan.period);
+            }
+            subscription.next_payment_at = next_payment_at;
+            self.subscriptions.insert(subscription_id, &subscription);
+            self.env().emit_event(PaymentCollected {
+                subscription_id,
+                amount: plan.amount,
+                transfer_id,
+                next_payment_at,
+            });
+            Ok(())
+        }
+
+        /// Cancels an active subscription. Either the subscriber or the merchant can cancel.
+        #[ink(message)]
+        pub fn cancel_subscription(&mut self, subscription_id: u64) -> Result<(), PSP22Error> {
+            let mut subscription = self.subscriptions.get(subscription_id).ok_or(PSP22Error::SubscriptionNotFound)?;
+            if subscription.status != SubscriptionStatus::Active {
+                return Err(PSP22Error::SubscriptionNotActive);
+            }
+            let plan = self.plans.get(subscription.plan_id).ok_or(PSP22Error::PlanNotFound)?;
+            let caller = Self::env().caller();
+            if caller != subscription.subscriber && caller != plan.merchant {
+                return Err(PSP22Error::Unauthorized);
+            }
+            subscription.status = SubscriptionStatus::Cancelled;
+            self.subscriptions.insert(subscription_id, &subscription);
+            self.env().emit_event(SubscriptionCancelled {
+                subscription_id,
+                cancelled_by: caller,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_plan(&self, plan_id: u64) -> Option<Plan> {
+            self.plans.get(plan_id)
+        }
+
+        #[ink(message)]
+        pub fn get_subscription(&self, subscription_id: u64) -> Option<Subscription> {
+            self.subscriptions.get(subscription_id)
+        }
+
+        /// Returns the most recent plans registered by `merchant`.
+        #[ink(message)]
+        pub fn plans_of(&self, merchant: AccountId) -> Vec<Plan> {
+            self.merchant_plans
+                .get(merchant)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.plans.get(id))
+                .collect()
+        }
+
+        /// Returns the most recent subscriptions to a plan.
+        #[ink(message)]
+        pub fn subscriptions_of_plan(&self, plan_id: u64) -> Vec<Subscription> {
+            self.plan_subscriptions
+                .get(plan_id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.subscriptions.get(id))
+                .collect()
+        }
+
+        /// Returns the most recent subscriptions of `subscriber`.
+        #[ink(message)]
+        pub fn subscriptions_of(&self, subscriber: AccountId) -> Vec<Subscription> {
+            self.subscriber_subscriptions
+                .get(subscriber)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.subscriptions.get(id))
+                .collect()
+        }
+
+        /// Streams `deposit` to `recipient` at `rate_per_ms` between the `start` and `stop`
+        /// timestamps. The deposit must be exactly `rate_per_ms * (stop - start)` and is held in
+        /// escrow, so it can neither be spent by the sender nor withdrawn before it accrues.
+        /// Returns the id of the stream.
+        #[ink(message)]
+        pub fn create_stream(
+            &mut self,
+            recipient: AccountId,
+            rate_per_ms: Balance,
+            deposit: Balance,
+            start: u64,
+            stop: u64,
+        ) -> Result<u64, PSP22Error> {
+            let sender = Self::env().caller();
+            if recipient == sender || start < Self::env().block_timestamp() || stop <= start {
+                return Err(PSP22Error::InvalidStream);
+            }
+            let expected = rate_per_ms.checked_mul(Balance::from(stop.saturating_sub(start)));
+            if rate_per_ms == 0 || expected != Some(deposit) {
+                return Err(PSP22Error::InvalidStream);
+            }
+            self.escrow_in(sender, deposit, Vec::new())?;
+            let id = Self::next_id(&mut self.last_stream_id);
+            self.streams.insert(id, &Stream {
+                id,
+                sender,
+                recipient,
+                deposit,
+                rate_per_ms,
+                start,
+                stop,
+                withdrawn: 0,
+                cancelled: false,
+            });
+            Self::index_id(&mut self.account_streams, sender, id, MAX_STREAMS_PER_ACCOUNT);
+            Self::index_id(&mut self.account_streams, recipient, id, MAX_STREAMS_PER_ACCOUNT);
+            self.env().emit_event(StreamCreated {
+                id,
+                sender,
+                recipient,
+                deposit,
+                rate_per_ms,
+                start,
+                stop,
+            });
+            Ok(id)
+        }
+
+        /// Withdraws `amount` of the accrued, not yet withdrawn part of a stream to its recipient.
+        #[ink(message)]
+        pub fn withdraw_from_stream(&mut self, id: u64, amount: Balance) -> Result<(), PSP22Error> {
+            let mut stream = self.streams.get(id).ok_or(PSP22Error::StreamNotFound)?;
+            if stream.cancelled {
+                return Err(PSP22Error::StreamNotActive);
+            }
+            if Self::env().caller() != stream.recipient {
+                return Err(PSP22Error::Unauthorized);
+            }
+            if amount > self.stream_withdrawable(&stream) {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.escrow_out(stream.recipient, amount, Vec::new())?;
+            stream.withdrawn = stream.withdrawn.saturating_add(amount);
+            self.streams.insert(id, &stream);
+            self.env().emit_event(StreamWithdrawn {
+                id,
+                recipient: stream.recipient,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Cancels a stream. The recipient receives what accrued and was not withdrawn yet, the
+        /// sender gets back the part that has not accrued. Either party can cancel.
+        #[ink(message)]
+        pub fn cancel_stream(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut stream = self.streams.get(id).ok_or(PSP22Error::StreamNotFound)?;
+            if stream.cancelled {
+                return Err(PSP22Error::StreamNotActive);
+            }
+            let caller = Self::env().caller();
+            if caller != stream.sender && caller != stream.recipient {
+                return Err(PSP22Error::Unauthorized);
+            }
+            let recipient_amount = self.stream_withdrawable(&stream);
+            let sender_amount = stream
+                .deposit
+                .saturating_sub(stream.withdrawn)
+                .saturating_sub(recipient_amount);
+            if recipient_amount > 0 {
+                self.escrow_out(stream.recipient, recipient_amount, Vec::new())?;
+            }
+            if sender_amount > 0 {
+                self.escrow_out(stream.sender, sender_amount, Vec::new())?;
+            }
+            stream.withdrawn = stream.withdrawn.saturating_add(recipient_amount);
+            stream.cancelled = true;
+            self.streams.insert(id, &stream);
+            self.env().emit_event(StreamCancelled {
+                id,
+                cancelled_by: caller,
+                recipient_amount,
+                sender_amount,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_stream(&self, id: u64) -> Option<Stream> {
+            self.streams.get(id)
+        }
+
+        /// Amount the recipient of a stream can withdraw right now.
+        #[ink(message)]
+        pub fn withdrawable_from_stream(&self, id: u64) -> Balance {
+            self.streams
+                .get(id)
+                .filter(|stream| !stream.cancelled)
+                .map(|stream| self.stream_withdrawable(&stream))
+                .unwrap_or(0)
+        }
+
+        /// Returns the most recent streams sent or received by `account`.
+        #[ink(message)]
+        pub fn streams_of(&self, account: AccountId) -> Vec<Stream> {
+            self.account_streams
+                .get(account)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|id| self.streams.get(id))
+                .collect()
+        }
+
+        /// Locks `amount` for `recipient` until `timelock`. The recipient is paid by revealing the
+        /// preimage of `hashlock`, either SHA-256 or Keccak-256. Returns the id of the lock.
+        #[ink(message)]
+        pub fn lock(&mut self, recipient: AccountId, amount: Balance, hashlock: Hashlock, timelock: u64) -> Result<u64, PSP22Error> {
+            if timelock <= Self::env().block_timestamp() {
+                return Err(PSP22Error::InvalidExpiry);
+            }
+            let sender = Self::env().caller();
+            self.escrow_in(sender, amount, Vec::new())?;
+            let id = Self::next_id(&mut self.last_htlc_id);
+            self.htlcs.insert(id, &Htlc {
+                id,
+                sender,
+                recipient,
+                amount,
+                hashlock,
+                timelock,
+                status: HtlcStatus::Locked,
+            });
+            self.env().emit_event(HtlcLocked {
+                id,
+                sender,
+                recipient,
+                amount,
+                hashlock,
+                timelock,
+            });
+            Ok(id)
+        }
+
+        /// Pays a lock to its recipient if `preimage` matches the hashlock and the timelock has
+        /// not passed. Anyone holding the preimage can redeem; it is revealed in `HtlcRedeemed`.
+        #[ink(message)]
+        pub fn redeem(&mut self, id: u64, preimage: Vec<u8>) -> Result<(), PSP22Error> {
+            let mut htlc = self.htlcs.get(id).ok_or(PSP22Error::HtlcNotFound)?;
+            if htlc.status != HtlcStatus::Locked {
+                return Err(PSP22Error::HtlcNotLocked);
+            }
+            if Self::env().block_timestamp() >= htlc.timelock {
+                return Err(PSP22Error::HtlcExpired);
+            }
+            let matches = match htlc.hashlock {
+                Hashlock::Sha2x256(hash) => self.env().hash_bytes::<Sha2x256>(&preimage) == hash,
+                Hashlock::Keccak256(hash) => self.env().hash_bytes::<Keccak256>(&preimage) == hash,
+            };
+            if !matches {
+                return Err(PSP22Error::InvalidPreimage);
+            }
+            self.escrow_out(htlc.recipient, htlc.amount, Vec::new())?;
+            htlc.status = HtlcStatus::Redeemed;
+            self.htlcs.insert(id, &htlc);
+            self.env().emit_event(HtlcRedeemed { id, preimage });
+            Ok(())
+        }
+
+        /// Returns a lock to its sender once the timelock has passed without a redeem.
+        #[ink(message)]
+        pub fn refund(&mut self, id: u64) -> Result<(), PSP22Error> {
+            let mut htlc = self.htlcs.get(id).ok_or(PSP22Error::HtlcNotFound)?;
+            if htlc.status != HtlcStatus::Locked {
+                return Err(PSP22Error::HtlcNotLocked);
+            }
+            if Self::env().block_timestamp() < htlc.timelock {
+                return Err(PSP22Error::HtlcNotExpired);
+            }
+            self.escrow_out(htlc.sender, htlc.amount, Vec::new())?;
+            htlc.status = HtlcStatus::Refunded;
+            self.htlcs.insert(id, &htlc);
+            self.env().emit_event(HtlcRefunded { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_htlc(&self, id: u64) -> Option<Htlc> {
+            self.htlcs.get(id)
+        }
+
+        /// Stakes `value` tokens of the caller, which then earn a share of the rewards.
+        #[ink(message)]
+        pub fn stake(&mut self, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let (mut pool, mut stake) = self.update_rewards(caller)?;
+            self.escrow_in(caller, value, Vec::new())?;
+            stake.amount = stake.amount.saturating_add(value);
+            pool.total_staked = pool.total_staked.saturating_add(value);
+            self.stakes.insert(caller, &stake);
+            self.staking_pool.set(&pool);
+            self.env().emit_event(Staked { account: caller, amount: value });
+            Ok(())
+        }
+
+        /// Unstakes `value` tokens of the caller. They are paid out right away without an
+        /// unbonding period, else added to the caller's unbonding amount, whose release is
+        /// pushed back to a full period from now.
+        #[ink(message)]
+        pub fn unstake(&mut self, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let (mut pool, mut stake) = self.update_rewards(caller)?;
+            if stake.amount < value {
+                return Err(PSP22Error::InsufficientStake);
+            }
+            stake.amount = stake.amount.saturating_sub(value);
+            pool.total_staked = pool.total_staked.saturating_sub(value);
+            let now = self.env().block_timestamp();
+            let release = now.saturating_add(pool.unbonding_period);
+            if release == now {
+                self.escrow_out(caller, value, Vec::new())?;
+            } else {
+                stake.unbonding = stake.unbonding.saturating_add(value);
+                stake.unbonding_release = release;
+            }
+            self.stakes.insert(caller, &stake);
+            self.staking_pool.set(&pool);
+            self.env().emit_event(Unstaked {
+                account: caller,
+                amount: value,
+                release,
+            });
+            Ok(())
+        }
+
+        /// Pays out the caller's unbonding amount once released.
+        #[ink(message)]
+        pub fn withdraw_unbonded(&mut self) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let mut stake = self.stakes.get(caller).unwrap_or_default();
+            if self.env().block_timestamp() < stake.unbonding_release {
+                return Err(PSP22Error::UnbondingNotDue);
+            }
+            let value = stake.unbonding;
+            if value == 0 {
+                return Err(PSP22Error::InsufficientStake);
+            }
+            stake.unbonding = 0;
+            self.stakes.insert(caller, &stake);
+            self.escrow_out(caller, value, Vec::new())?;
+            self.env().emit_event(Withdrawn {
+                account: caller,
+                amount: value,
+            });
+            Ok(())
+        }
+
+        /// Pays out the rewards accrued by the caller. They were taken from the reward pool
+        /// as they accrued, so a claim never exceeds what was funded.
+        #[ink(message)]
+        pub fn claim_rewards(&mut self) -> Result<Balance, PSP22Error> {
+            let caller = self.env().caller();
+            let (pool, mut stake) = self.update_rewards(caller)?;
+            let rewards = stake.rewards;
+            stake.rewards = 0;
+            self.stakes.insert(caller, &stake);
+            self.staking_pool.set(&pool);
+            if rewards > 0 {
+                self.escrow_out(caller, rewards, Vec::new())?;
+            }
+            self.env().emit_event(RewardsClaimed {
+                account: caller,
+                amount: rewards,
+            });
+            Ok(rewards)
+        }
+
+        /// Adds `value` tokens of the caller to the pool rewards are paid from.
+        #[ink(message)]
+        pub fn fund_rewards(&mut self, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            self.escrow_in(caller, value, Vec::new())?;
+            // Rewards up to now accrue from the previous funds only
+            let mut pool = self.accrued_pool()?;
+            pool.reward_pool = pool.reward_pool.saturating_add(value);
+            self.staking_pool.set(&pool);
+            self.env().emit_event(RewardsFunded { funder: caller, amount: value });
+            Ok(())
+        }
+
+        /// Sets the rewards paid per second to all stakers together. Admin only.
+        #[ink(message)]
+        pub fn set_reward_rate(&mut self, new_rate: Balance) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            // Rewards up to now accrue at the old rate
+            let mut pool = self.accrued_pool()?;
+            let old_rate = pool.reward_rate;
+            pool.reward_rate = new_rate;
+            self.staking_pool.set(&pool);
+            self.env().emit_event(RewardRateChanged { old_rate, new_rate });
+            Ok(())
+        }
+
+        /// Sets the time in ms between unstaking and withdrawing. Admin only.
+        #[ink(message)]
+        pub fn set_unbonding_period(&mut self, period: u64) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            let mut pool = self.staking_pool();
+            let old = pool.unbonding_period;
+            pool.unbonding_period = period;
+            self.staking_pool.set(&pool);
+            self.env().emit_event(UnbondingPeriodChanged { old, new: period });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn staking_pool(&self) -> StakingPool {
+            self.staking_pool.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        pub fn get_stake(&self, account: AccountId) -> Stake {
+            self.stakes.get(account).unwrap_or_default()
+        }
+
+        /// Rewards accrued by `account` and not claimed yet.
+        #[ink(message)]
+        pub fn earned(&self, account: AccountId) -> Result<Balance, PSP22Error> {
+            Ok(Self::accrue(&self.accrued_pool()?, self.get_stake(account))?.rewards)
+        }
+
+        /// Allows or disallows `manager` to place and release holds. Admin only.
+        #[ink(message)]
+        pub fn set_hold_manager(&mut self, manager: AccountId, allowed: bool) -> Result<(), PSP22Error> {
+            self.ensure_owner()?;
+            if allowed {
+                self.hold_managers.insert(manager, &());
+            } else {
+                self.hold_managers.remove(manager);
+            }
+            self.env().emit_event(HoldManagerUpdated { manager, allowed });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn is_hold_manager(&self, account: AccountId) -> bool {
+            self.hold_managers.contains(account)
+        }
+
+        /// Holds `amount` of the spendable balance of `account` under `reason`: the tokens stay
+        /// in the account but cannot be moved until released. Hold managers only.
+        #[ink(message)]
+        pub fn hold(&mut self, account: AccountId, amount: Balance, reason: u32) -> Result<(), PSP22Error> {
+            let manager = self.ensure_hold_manager()?;
+            if self.spendable_balance_of(account) < amount {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.held.insert(account, &self.held_balance_of(account).saturating_add(amount));
+            let held = self.hold_of(account, manager, reason).saturating_add(amount);
+            self.holds.insert((account, manager, reason), &held);
+            self.env().emit_event(Held {
+                account,
+                manager,
+                reason,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Releases `amount` held by the caller on `account` under `reason`. Hold managers only.
+        #[ink(message)]
+        pub fn release_hold(&mut self, account: AccountId, amount: Balance, reason: u32) -> Result<(), PSP22Error> {
+            let manager = self.ensure_hold_manager()?;
+            let held = self.hold_of(account, manager, reason);
+            if held < amount {
+                return Err(PSP22Error::InsufficientHold);
+            }
+            if held == amount {
+                self.holds.remove((account, manager, reason));
+            } else {
+                self.holds.insert((account, manager, reason), &held.saturating_sub(amount));
+            }
+            let total = self.held_balance_of(account).saturating_sub(amount);
+            if total == 0 {
+                self.held.remove(account);
+            } else {
+                self.held.insert(account, &total);
+            }
+            self.env().emit_event(HoldReleased {
+                account,
+                manager,
+                reason,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// Part of the balance of `account` that can be transferred or burned.
+        #[ink(message)]
+        pub fn spendable_balance_of(&self, account: AccountId) -> Balance {
+            self.balance_of(account).saturating_sub(self.held_balance_of(account))
+        }
+
+        /// Part of the balance of `account` held under any reason.
+        #[ink(message)]
+        pub fn held_balance_of(&self, account: AccountId) -> Balance {
+            self.held.get(account).unwrap_or(0)
+        }
+
+        /// Amount held by `manager` on `account` under `reason`.
+        #[ink(message)]
+        pub fn hold_of(&self, account: AccountId, manager: AccountId, reason: u32) -> Balance {
+            self.holds.get((account, manager, reason)).unwrap_or(0)
+        }
+
+        // Helper function to check that the caller may manage holds
+        fn ensure_hold_manager(&self) -> Result<AccountId, PSP22Error> {
+            let caller = self.env().caller();
+            if !self.hold_managers.contains(caller) {
+                return Err(PSP22Error::Unauthorized);
+            }
+            Ok(caller)
+        }
+
+        // Helper function to bring the reward per token up to now. The rewards distributed
+        // are capped at the reward pool and taken out of it.
+        fn accrued_pool(&self) -> Result<StakingPool, PSP22Error> {
+            let mut pool = self.staking_pool();
+            let now = self.env().block_timestamp();
+            if pool.total_staked > 0 {
+                let elapsed = Balance::from(now.saturating_sub(pool.last_update));
+                let rewards = elapsed
+                    .checked_mul(pool.reward_rate)
+                    .ok_or(PSP22Error::Overflow)?
+                    .checked_div(1_000)
+                    .unwrap_or(0)
+                    .min(pool.reward_pool);
+                let increase = rewards
+                    .checked_mul(REWARD_PRECISION)
+                    .ok_or(PSP22Error::Overflow)?
+                    .checked_div(pool.total_staked)
+                    .unwrap_or(0);
+                // Rounded up, so the rewards claimed in total never exceed the funds
+                let distributed = increase
+                    .checked_mul(pool.total_staked)
+                    .ok_or(PSP22Error::Overflow)?
+                    .div_ceil(REWARD_PRECISION);
+                pool.reward_per_token = pool.reward_per_token.checked_add(increase).ok_or(PSP22Error::Overflow)?;
+                pool.reward_pool = pool.reward_pool.saturating_sub(distributed);
+            }
+            pool.last_update = now;
+            Ok(pool)
+        }
+
+        // Helper function to credit a stake with its rewards up to the pool's reward per token
+        fn accrue(pool: &StakingPool, mut stake: Stake) -> Result<Stake, PSP22Error> {
+            let earned = stake
+                .amount
+                .checked_mul(pool.reward_per_token.saturating_sub(stake.reward_per_token_paid))
+                .ok_or(PSP22Error::Overflow)?
+                .checked_div(REWARD_PRECISION)
+                .unwrap_or(0);
+            stake.rewards = stake.rewards.checked_add(earned).ok_or(PSP22Error::Overflow)?;
+            stake.reward_per_token_paid = pool.reward_per_token;
+            Ok(stake)
+        }
+
+        // Helper function to update the pool and an account's rewards before its stake changes
+        fn update_rewards(&self, account: AccountId) -> Result<(StakingPool, Stake), PSP22Error> {
+            let pool = self.accrued_pool()?;
+            let stake = Self::accrue(&pool, self.get_stake(account))?;
+            Ok((pool, stake))
+        }
+
+        // Helper function to compute the accrued, not yet withdrawn part of a stream
+        fn stream_withdrawable(&self, stream: &Stream) -> Balance {
+            let now = Self::env().block_timestamp().min(stream.stop);
+            let elapsed = Balance::from(now.saturating_sub(stream.start));
+            stream
+                .rate_per_ms
+                .saturating_mul(elapsed)
+                .min(stream.deposit)
+                .saturating_sub(stream.withdrawn)
+        }
+
+        // Helper function to bound the size of metadata fields
+        fn ensure_metadata_length(value: &str) -> Result<(), PSP22Error> {
+            if value.len() > MAX_METADATA_LENGTH {
+                return Err(PSP22Error::MetadataTooLong);
+            }
+            Ok(())
+        }
+
+        // Helper function to emit the MetadataUpdated event
+        fn emit_metadata_updated(&self) {
+            self.env().emit_event(MetadataUpdated {
+                metadata: self.metadata(),
+            });
+        }
+
+        // Helper function to check that the caller is the owner
+        fn ensure_owner(&self) -> Result<(), PSP22Error> {
+            if self.owner() != Some(Self::env().caller()) {
+                return Err(PSP22Error::NotOwner);
+            }
+            Ok(())
+        }
+
+        // Helper function to clear a tip from the pending totals
+        fn settle_tip(&mut self, tip: &Tip) {
+            self.pending_tips_out.insert(tip.sender, &self.pending_tips_out.get(tip.sender).unwrap_or(0).saturating_sub(tip.amount));
+            self.pending_tips_in.insert(tip.recipient, &self.pending_tips_in.get(tip.recipient).unwrap_or(0).saturating_sub(tip.amount));
+        }
+
+        // Helper function to move tokens from `from` into the contract-held escrow
+        fn escrow_in(&mut self, from: AccountId, value: Balance, memo: Vec<u8>) -> Result<u64, PSP22Error> {
+            let id = self.move_tokens(from, Self::env().account_id(), value, memo)?;
+            self.escrowed.set(&self.escrowed().saturating_add(value));
+            Ok(id)
+        }
+
+        // Helper function to release tokens from the contract-held escrow to `to`
+        fn escrow_out(&mut self, to: AccountId, value: Balance, memo: Vec<u8>) -> Result<u64, PSP22Error> {
+            let id = self.move_tokens(Self::env().account_id(), to, value, memo)?;
+            self.escrowed.set(&self.escrowed().saturating_sub(value));
+            Ok(id)
+        }
+
+        // Helper function to take back a flash loan and its fee out of the allowance the
+        // receiver gave this contract, burning them
+        fn repay_flashloan(&mut self, receiver: AccountId, repayment: Balance) -> Result<(), PSP22Error> {
+            let this = self.env().account_id();
+            let allowance = self.live_allowance(receiver, this);
+            if allowance.amount < repayment {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.set_allowance(receiver, this, allowance.amount.saturating_sub(repayment), allowance.expires_at);
+            self.burn_from(receiver, repayment)
+        }
+
+        // Helper function to read the tokens held in escrow
+        fn escrowed(&self) -> Balance {
+            self.escrowed.get().unwrap_or(0)
+        }
+
+        // Helper function to take the next id from a counter
+        fn next_id<K: StorageKey>(cell: &mut Lazy<u64, K>) -> u64 {
+            let id = cell.get().unwrap_or(0).saturating_add(1);
+            cell.set(&id);
+            id
+        }
+
+        // Helper function to index an id under a key, dropping the oldest one when full
+        fn index_id<T: scale::EncodeLike + Copy, K: StorageKey>(index: &mut Mapping<T, Vec<u64>, K>, key: T, id: u64, max: usize) {
+            let mut ids = index.get(key).unwrap_or_default();
+            if ids.len() >= max {
+                ids.remove(0);
+            }
+            ids.push(id);
+            index.insert(key, &ids);
+        }
+
+        // Helper function to move tokens between accounts, recording and emitting the transfer.
+        // Returns the transfer log id.
+        fn move_tokens(&mut self, from: AccountId, to: AccountId, value: Balance, memo: Vec<u8>) -> Result<u64, PSP22Error> {
+            self.update_balances(from, to, value)?;
+            let id = self.log_transfer(Some(from), Some(to), value, memo.clone());
+            self.record_transfer(from, to, value, id, memo);
+            self._emit_transfer_event(Some(from), Some(to), value, id);
+            Ok(id)
+        }
+
+        // Helper function to append a movement to the global transfer log, returns its id
+        fn log_transfer(&mut self, from: Option<AccountId>, to: Option<AccountId>, value: Balance, memo: Vec<u8>) -> u64 {
+            let id = Self::next_id(&mut self.last_transfer_id);
+            self.transfer_log.insert(id, &TransferRecord {
+                id,
+                from,
+                to,
+                value,
+                timestamp: Self::env().block_timestamp(),
+                memo,
+            });
+            // Drop the oldest entry once the log is full
+            if id > MAX_TRANSFER_LOG_SIZE {
+                self.transfer_log.remove(id.saturating_sub(MAX_TRANSFER_LOG_SIZE));
+            }
+            id
+        }
+         // Helper function to record transfers
+         fn record_transfer(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance, id: u64, memo: Vec<u8>) {
+            let record = HistoryRecord {
+                id,
+                from,
+                to,
+                value,
+                timestamp: Self::env().block_timestamp(),
+                memo,
+            };
+            
+            // Record transfer in sender's history
+            self.push_history(from, record.clone());
+            // Record transfer in recipient's history
+            self.push_history(to, record);
+        }
+
+        // Helper function to append a record to an account's history
+        fn push_history(&mut self, account: DefaultAccountId, record: HistoryRecord) {
+            history::push(&mut self.history, &mut self.history_count, account, &record);
+        }
+
+        // Helper function to read an account's history, oldest first
+        fn read_history(&self, account: DefaultAccountId) -> Vec<HistoryRecord> {
+            history::read(&self.history, &self.history_count, account)
+        }
+
+        // Helper function to write a balance, keeping the holder set in sync. Zero balances
+        // are removed from storage and their holder swap-removed from the set.
+        fn set_balance(&mut self, account: DefaultAccountId, balance: DefaultBalance) {
+            if balance == 0 {
+                self.balances.remove(account);
+                if let Some(index) = self.holder_index.take(account) {
+                    let last = self.holder_count().saturating_sub(1);
+                    if index != last {
+                        if let Some(moved) = self.holders.get(last) {
+                            self.holders.insert(index, &moved);
+                            self.holder_index.insert(moved, &index);
+                        }
+                    }
+                    self.holders.remove(last);
+                    self.holder_count.set(&last);
+                }
+                return;
+            }
+            if !self.holder_index.contains(account) {
+                let count = self.holder_count();
+                self.holders.insert(count, &account);
+                self.holder_index.insert(account, &count);
+                self.holder_count.set(&count.saturating_add(1));
+            }
+            self.balances.insert(account, &balance);
+        }
+
+        // Helper function to read an allowance as stored, including expired ones
+        fn allowance_entry(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> Allowance {
+            Allowance {
+                amount: self.allowances.get((owner, spender)).unwrap_or(0),
+                expires_at: self.allowance_expiries.get((owner, spender)),
+            }
+        }
+
+        // Helper function to read an allowance, expired ones counting as zero
+        fn live_allowance(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> Allowance {
+            let entry = self.allowance_entry(owner, spender);
+            if entry.is_expired(self.env().block_timestamp()) {
+                return Allowance::default();
+            }
+            entry
+        }
+
+        // Helper function to enforce strict mode, which only allows changes to or from zero
+        fn ensure_approve_allowed(&self, owner: DefaultAccountId, spender: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
+            if value != 0 && self.strict_approvals.contains(owner) && self.allowance(owner, spender) != 0 {
+                return Err(PSP22Error::AllowanceNotZero);
+            }
+            Ok(())
+        }
+
+        // Helper function to write an allowance and emit its Approval event, keeping the owner's
+        // spender set in sync the same way `set_balance` does for holders
+        fn set_allowance(
+            &mut self,
+            owner: DefaultAccountId,
+            spender: DefaultAccountId,
+            value: DefaultBalance,
+            expires_at: Option<u64>,
+        ) {
+            self._emit_approval_event(owner, spender, value);
+            if value == 0 {
+                self.allowances.remove((owner, spender));
+                self.allowance_expiries.remove((owner, spender));
+                if let Some(index) = self.spender_index.take((owner, spender)) {
+                    let last = self.spender_count.get(owner).unwrap_or(0).saturating_sub(1);
+                    if index != last {
+                        if let Some(moved) = self.spenders.get((owner, last)) {
+                            self.spenders.insert((owner, index), &moved);
+                            self.spender_index.insert((owner, moved), &index);
+                        }
+                    }
+                    self.spenders.remove((owner, last));
+                    self.spender_count.insert(owner, &last);
+                }
+                return;
+            }
+            self.index_spender(owner, spender);
+            self.allowances.insert((owner, spender), &value);
+            match expires_at {
+                Some(expires_at) => {
+                    self.allowance_expiries.insert((owner, spender), &expires_at);
+                }
+                None => self.allowance_expiries.remove((owner, spender)),
+            }
+        }
+
+        // Helper function to add a spender to the owner's spender set if not there yet
+        fn index_spender(&mut self, owner: DefaultAccountId, spender: DefaultAccountId) {
+            if self.spender_index.contains((owner, spender)) {
+                return;
+            }
+            let count = self.spender_count.get(owner).unwrap_or(0);
+            self.spenders.insert((owner, count), &spender);
+            self.spender_index.insert((owner, spender), &count);
+            self.spender_count.insert(owner, &count.saturating_add(1));
+        }
+
+        // Helper function to mint new tokens
+        fn mint_to(&mut self, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
+            let total_supply = self.total_supply().checked_add(value).ok_or(PSP22Error::Overflow)?;
+            self.total_supply.set(&total_supply);
+            self.set_balance(to, self.balances.get(to).unwrap_or(0).saturating_add(value));
+            let id = self.log_transfer(None, Some(to), value, Vec::new());
+            self._emit_transfer_event(None, Some(to), value, id);
+            Ok(())
+        }
+
+        // Helper function to burn tokens
+        fn burn_from(&mut self, from: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
+            if self.spendable_balance_of(from) < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.set_balance(from, self.balance_of(from).saturating_sub(value));
+            self.total_supply.set(&self.total_supply().saturating_sub(value));
+            let id = self.log_transfer(Some(from), None, value, Vec::new());
+            self._emit_transfer_event(Some(from), None, value, id);
+            Ok(())
+        }
+
+        // Helper function to update balances
+        fn update_balances(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
+            // Held tokens stay in place
+            if self.spendable_balance_of(from) < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+
+            self.set_balance(from, self.balance_of(from).saturating_sub(value));
+            self.set_balance(to, self.balances.get(to).unwrap_or(0).saturating_add(value));
+            Ok(())
+        }
+        // Helper function to emit transfer events
+        fn _emit_transfer_event(
+            &self,
+            from: Option<AccountId>,
+            to: Option<AccountId>,
+            amount: Balance,
+            id: u64,
+        ) {
+            self.env().emit_event(Transfer {
+                from,
+                to,
+                value: amount,
+                id,
+            });
+        }
+        // Helper function to emit approval events
+        fn _emit_approval_event(&self, owner: AccountId, spender: AccountId, amount: Balance) {
+            self.env().emit_event(Approval {
+                owner,
+                spender,
+                value: amount,
+            });
+        }
+       
+    }
+
+    impl Psp22 for PidChatPSP22 {
+        #[ink(message)]
+        fn token_name(&self) -> Vec<u8> {
+            self.name.get().unwrap_or_default().into()
+        }
+
+        #[ink(message)]
+        fn token_symbol(&self) -> Vec<u8> {
+            self.symbol.get().unwrap_or_default().into()
+        }
+
+        #[ink(message)]
+        fn token_decimals(&self) -> u8 {
+            self.decimals.get().unwrap_or_default()
+        }
+        #[ink(message)]
+        fn total_supply(&self) -> DefaultBalance {
+            self.total_supply.get().unwrap_or_default()
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: DefaultAccountId) -> DefaultBalance {
+            self.balances.get(owner).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: DefaultAccountId, spender: DefaultAccountId) -> DefaultBalance {
+            self.live_allowance(owner, spender).amount
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: DefaultAccountId, value: DefaultBalance) -> Result<(), PSP22Error> {
+            let caller = Self::env().caller();
+            // Update balances using helper function
+            self.update_balances(caller, to, value)?;
+            let id = self.log_transfer(Some(caller), Some(to), value, Vec::new());
+            // Record transfer using helper function
+            self.record_transfer(caller, to, value, id, Vec::new());
+            // Emit transfer event using helper function
+            self._emit_transfer_event(Some(caller), Some(to), value, id);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: DefaultAccountId, to: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
+            let caller = Self::env().caller();
+            
+            // Check allowance
+            let allowance = self.allowance_entry(from, caller);
+            if allowance.is_expired(self.env().block_timestamp()) {
+                return Err(PSP22Error::AllowanceExpired);
+            }
+            if allowance.amount < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            
+            // Update balances using helper function
+            self.update_balances(from, to, value)?;
+            // Remove allowance after successful transfer
+            self.set_allowance(from, caller, 0, None);
+            let id = self.log_transfer(Some(from), Some(to), value, Vec::new());
+            // Record transfer using helper function
+            self.record_transfer(from, to, value, id, Vec::new());
+            // Emit transfer event using helper function
+            self._emit_transfer_event(Some(from), Some(to), value, id);
+            Ok(())
+             
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
+            let caller = Self::env().caller();
+            self.ensure_approve_allowed(caller, spender, value)?;
+            self.set_allowance(caller, spender, value, None);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
+            let caller = Self::env().caller();
+            // An expired allowance restarts from zero without expiry
+            let allowance = self.live_allowance(caller, spender);
+            //update the allowance
+            self.set_allowance(caller, spender, allowance.amount.saturating_add(value), allowance.expires_at);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
+            let caller = Self::env().caller();
+            let allowance = self.live_allowance(caller, spender);
+            if allowance.amount < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            //update the allowance
+            self.set_allowance(caller, spender, allowance.amount.saturating_sub(value), allowance.expires_at);
+            Ok(())
+        }
+
+        #[ink(message)]
+        #[allow(unused_variables)]
+        fn burn(&mut self, from: DefaultAccountId, value: DefaultBalance) -> Result<(),PSP22Error> {
+            let caller = Self::env().caller();
+            self.burn_from(caller, value)
+        }
+        #[ink(message)]
+        fn history(&self, page: u32, limit: u32) -> Vec<HistoryRecord> {
+            let caller = self.env().caller();
+            let transfers = self.read_history(caller);
+            
+            // Validate pagination parameters
+            if page == 0 || limit == 0 {
+                return Vec::new();
+            }
+
+            // Calculate pagination indices with overflow protection
+            let start = (page.saturating_sub(1) as usize).saturating_mul(limit as usize);
+            if start >= transfers.len() {
+                return Vec::new();
+            }
+
+            let end = start.saturating_add(limit as usize).min(transfers.len());
+            
+            // Return the requested slice of history
+            transfers[start..end].to_vec()
+        }
+        
+    }
+    #[cfg(test)]
+    #[allow(clippy::arithmetic_side_effects)]
+    mod tests {
+        use super::*;    
+
+        use ink::env::test::{default_accounts, set_callee, set_caller}; 
+        use ink::env::DefaultEnvironment;
+        use crate::Environment;
+        type Balance = <DefaultEnvironment as Environment>::Balance;
+        type Event = <PidChatPSP22 as ink::reflect::ContractEventBase>::Type;
+
+        // Helper function to setup test environment
+        fn setup() -> PidChatPSP22 {        
+            let total_supply: Balance = 1_000_000;
+            // The off-chain contract account defaults to Alice, use a dedicated one
+            set_callee::<DefaultEnvironment>(AccountId::from([0xFF; 32]));
+            PidChatPSP22::new(
+                total_supply,
+                Some("TestToken".to_string()),
+                Some("TST".to_string()),
+                18,
+            )
+        }
+
+        // Helper function to hash a secret with SHA-256
+        fn sha2_256(input: &[u8]) -> [u8; 32] {
+            let mut output = [0u8; 32];
+            ink::env::hash_bytes::<Sha2x256>(input, &mut output);
+            output
+        }
+
+        // Test basic token information
+        #[ink::test]
+        fn test_token_info() {
+            let contract = setup();
+            
+            assert_eq!(contract.token_name(), "TestToken".as_bytes().to_vec());
+            assert_eq!(contract.token_symbol(), "TST".as_bytes().to_vec());
+            assert_eq!(contract.token_decimals(), 18);
+            assert_eq!(contract.total_supply(), 1_000_000);
+        }
+
+        // Test initial balance
+        #[ink::test]
+        fn test_initial_balance() {
+            let contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000);
+            assert_eq!(contract.balance_of(accounts.bob), 0);
+        }
+
+        // Test transfer
+        #[ink::test]
+        fn test_transfer() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Transfer 100 tokens from Alice to Bob
+            assert!(contract.transfer(accounts.bob, 100).is_ok());
+            
+            // Check balances after transfer
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+        }
+
+        // Test transfer with insufficient balance
+        #[ink::test]
+        fn test_transfer_insufficient_balance() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Try to transfer more than available balance
+            let result = contract.transfer(accounts.bob, 2_000_000);
+            assert_eq!(result, Err(PSP22Error::InsufficientBalance));
+        }
+
+        // Test approve and allowance
+        #[ink::test]
+        fn test_approve_and_allowance() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Approve Bob to spend 500 tokens
+            assert!(contract.approve(accounts.bob, 500).is_ok());
+            
+            // Check allowance
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 500);
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let event = <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap();
+            assert!(matches!(
+                event,
+                Event::Approval(Approval { owner, spender, value })
+                    if owner == accounts.alice && spender == accounts.bob && value == 500
+            ));
+        }
+
+        // Test transfer_from
+        #[ink::test]
+        fn test_transfer_from() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Alice approves Bob to spend 500 tokens
+            assert!(contract.approve(accounts.bob, 500).is_ok());
+            
+            // Bob transfers 300 tokens from Alice to Charlie
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer_from(accounts.alice, accounts.charlie, 300).is_ok());
+            
+            // Check balances
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 300);
+            assert_eq!(contract.balance_of(accounts.charlie), 300);
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 0);
+        }
+
+        // Test increase/decrease allowance
+        #[ink::test]
+        fn test_allowance_modifications() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Initial approve
+            assert!(contract.approve(accounts.bob, 500).is_ok());
+            
+            // Increase allowance
+            assert!(contract.increase_allowance(accounts.bob, 200).is_ok());
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 700);
+            
+            // Decrease allowance
+            assert!(contract.decrease_allowance(accounts.bob, 300).is_ok());
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 400);
+        }
+
+        // Test burn
+        #[ink::test]
+        fn test_burn() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Burn 100 tokens
+            assert!(contract.burn(accounts.alice, 100).is_ok());
+            
+            // Check balance and total supply
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
+            assert_eq!(contract.total_supply(), 1_000_000 - 100);
+        }
+
+        // Test transfer history
+        #[ink::test]
+        fn test_transfer_history() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Make some transfers
+            assert!(contract.transfer(accounts.bob, 100).is_ok());
+            assert!(contract.transfer(accounts.charlie, 200).is_ok());
+            
+            // Check history for Alice
+            let history = contract.history(1, 10);
+            assert_eq!(history.len(), 2);
+            
+            // Verify first transfer details
+            assert_eq!(history[0].from, accounts.alice);
+            assert_eq!(history[0].to, accounts.bob);
+            assert_eq!(history[0].value, 100);
+            assert_eq!(history[0].id, 2);
+        }
+
+        // Test history pagination
+        #[ink::test]
+        fn test_history_pagination() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            
+            // Make multiple transfers
+            for i in 0..5 {
+                assert!(contract.transfer(accounts.bob, 100 + i).is_ok());
+            }
+            
+            // Test first page
+            let page1 = contract.history(1, 2);
+            assert_eq!(page1.len(), 2);
+            
+            // Test second page
+            let page2 = contract.history(2, 2);
+            assert_eq!(page2.len(), 2);
+            
+            // Test last page
+            let page3 = contract.history(3, 2);
+            assert_eq!(page3.len(), 1);
+            
+            // Test invalid page
+            let invalid_page = contract.history(10, 2);
+            assert_eq!(invalid_page.len(), 0);
+        }
+
+        // Test maximum history size
+        #[ink::test]
+        fn test_max_history_size() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            // Make more transfers than MAX_HISTORY_SIZE
+            for i in 0..200 {
+                assert!(contract.transfer(accounts.bob, 100 + i).is_ok());
+            }
+            
+            // Check that history is limited to MAX_HISTORY_SIZE
+            let history = contract.history(1, 100);
+            assert_eq!(history.len(), 100); // Assuming MAX_HISTORY_SIZE is 100
+        }
+
+        // Test global transfer log ids
+        #[ink::test]
+        fn test_transfer_log() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // The initial mint is the first entry
+            assert_eq!(contract.last_transfer_id(), 1);
+            assert!(contract.transfer(accounts.bob, 100).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer(accounts.charlie, 40).is_ok());
+            assert!(contract.burn(accounts.bob, 10).is_ok());
+            assert_eq!(contract.last_transfer_id(), 4);
+
+            // Resume after the mint
+            let log = contract.transfers_since(1, 10);
+            assert_eq!(log.len(), 3);
+            assert_eq!(log[0].id, 2);
+            assert_eq!(log[0].from, Some(accounts.alice));
+            assert_eq!(log[1].to, Some(accounts.charlie));
+            assert_eq!(log[2].to, None);
+            assert_eq!(log[2].value, 10);
+
+            // Limit and end of feed
+            assert_eq!(contract.transfers_since(0, 2).len(), 2);
+            assert_eq!(contract.transfers_since(4, 10).len(), 0);
+        }
+
+        // Test transfer with memo
+        #[ink::test]
+        fn test_transfer_with_memo() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let memo = b"chat:42/msg:7".to_vec();
+            assert!(contract.transfer_with_memo(accounts.bob, 50, memo.clone()).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 50);
+
+            // The memo is kept in the log entry and in both histories
+            let log = contract.transfers_since(1, 1);
+            assert_eq!(log[0].memo, memo);
+            assert_eq!(contract.history(1, 10)[0].memo, memo);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.history(1, 10)[0].memo, memo);
+
+            // TransferWithMemo carries the memo and is indexed by its hash
+            let mut memo_hash = [0u8; 32];
+            ink::env::hash_bytes::<Blake2x256>(&memo, &mut memo_hash);
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let Ok(Event::TransferWithMemo(emitted)) = <Event as scale::Decode>::decode(&mut &event.data[..]) else {
+                panic!("expected TransferWithMemo");
+            };
+            assert_eq!((emitted.from, emitted.to, emitted.value, emitted.id), (accounts.alice, accounts.bob, 50, 2));
+            assert_eq!(emitted.memo_hash, memo_hash);
+            assert_eq!(emitted.memo, memo);
+            // Topics hash the field with its prefix, as the ink code generation does
+            let mut topic = [0u8; 32];
+            ink::env::hash_encoded::<Blake2x256, _>(&(b"PidChatPSP22::TransferWithMemo::memo_hash".to_vec(), memo_hash), &mut topic);
+            assert_eq!(event.topics[3], topic.to_vec());
+        }
+
+        // Test memo length bound
+        #[ink::test]
+        fn test_transfer_with_memo_too_long() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let memo = vec![0u8; MAX_MEMO_LENGTH + 1];
+            assert_eq!(contract.transfer_with_memo(accounts.bob, 50, memo), Err(PSP22Error::MemoTooLong));
+            assert_eq!(contract.balance_of(accounts.bob), 0);
+        }
+
+        // Test payment request lifecycle
+        #[ink::test]
+        fn test_payment_request() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // Bob asks Alice for 50 tokens
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let id = contract.create_request(accounts.alice, 50, b"lunch".to_vec(), 1_000).unwrap();
+            assert_eq!(contract.requests_for(accounts.alice).len(), 1);
+            assert_eq!(contract.requests_for(accounts.bob)[0].id, id);
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.pay_request(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 50);
+            assert_eq!(contract.get_request(id).unwrap().status, RequestStatus::Paid);
+            assert_eq!(contract.transfers_since(1, 1)[0].memo, b"lunch".to_vec());
+
+            // A request can only be paid once
+            assert_eq!(contract.pay_request(id), Err(PSP22Error::RequestNotPending));
+            assert_eq!(contract.balance_of(accounts.bob), 50);
+        }
+
+        // Test payment request by someone else than the payer
+        #[ink::test]
+        fn test_payment_request_wrong_payer() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let id = contract.create_request(accounts.charlie, 50, Vec::new(), 1_000).unwrap();
+
+            // Alice is not the payer
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.pay_request(id), Err(PSP22Error::WrongPayer));
+            assert_eq!(contract.cancel_request(id), Err(PSP22Error::Unauthorized));
+            assert_eq!(contract.pay_request(id + 1), Err(PSP22Error::RequestNotFound));
+        }
+
+        // Test payment request expiry and cancellation
+        #[ink::test]
+        fn test_payment_request_expiry() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.create_request(accounts.alice, 50, Vec::new(), 0), Err(PSP22Error::InvalidExpiry));
+            let expiring = contract.create_request(accounts.alice, 50, Vec::new(), 1_000).unwrap();
+            let cancelled = contract.create_request(accounts.alice, 50, Vec::new(), 1_000).unwrap();
+            assert!(contract.cancel_request(cancelled).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_001);
+            assert_eq!(contract.pay_request(expiring), Err(PSP22Error::RequestExpired));
+            assert_eq!(contract.pay_request(cancelled), Err(PSP22Error::RequestNotPending));
+            assert_eq!(contract.balance_of(accounts.bob), 0);
+        }
+
+        // Test escrowed tip accepted by the recipient
+        #[ink::test]
+        fn test_tip_accept() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let id = contract.send_tip(accounts.bob, 100, b"gm".to_vec(), 1_000).unwrap();
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
+            assert_eq!(contract.balance_of(accounts.bob), 0);
+            assert_eq!(contract.total_supply(), 1_000_000);
+            assert_eq!(contract.pending_outgoing_tips(accounts.alice), 100);
+            assert_eq!(contract.pending_incoming_tips(accounts.bob), 100);
+            assert_eq!(contract.tips_for(accounts.bob).len(), 1);
+
+            // Only the recipient can accept
+            assert_eq!(contract.accept_tip(id), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.accept_tip(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+            assert_eq!(contract.pending_incoming_tips(accounts.bob), 0);
+            assert_eq!(contract.pending_outgoing_tips(accounts.alice), 0);
+            assert_eq!(contract.accept_tip(id), Err(PSP22Error::TipNotPending));
+        }
+
+        // Test escrowed tip reclaimed by the sender after expiry
+        #[ink::test]
+        fn test_tip_reclaim() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let id = contract.send_tip(accounts.bob, 100, Vec::new(), 1_000).unwrap();
+            assert_eq!(contract.reclaim_tip(id), Err(PSP22Error::TipNotExpired));
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_001);
+            assert!(contract.reclaim_tip(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000);
+            assert_eq!(contract.pending_incoming_tips(accounts.bob), 0);
+            assert_eq!(contract.get_tip(id).unwrap().status, TipStatus::Reclaimed);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.accept_tip(id), Err(PSP22Error::TipNotPending));
+        }
+
+        // Test equal red envelope
+        #[ink::test]
+        fn test_envelope_equal_split() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let secret = b"gong xi fa cai".to_vec();
+            let secret_hash = sha2_256(&secret);
+            let id = contract.create_envelope(100, 3, SplitMode::Equal, secret_hash, 1_000).unwrap();
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 100);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.claim_envelope(id, b"wrong".to_vec()), Err(PSP22Error::InvalidSecret));
+            assert_eq!(contract.claim_envelope(id, secret.clone()), Ok(33));
+            assert_eq!(contract.claim_envelope(id, secret.clone()), Err(PSP22Error::AlreadyClaimed));
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.claim_envelope(id, secret.clone()), Ok(33));
+            // The last claimer takes the remainder
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert_eq!(contract.claim_envelope(id, secret.clone()), Ok(34));
+            set_caller::<DefaultEnvironment>(accounts.eve);
+            assert_eq!(contract.claim_envelope(id, secret), Err(PSP22Error::EnvelopeEmpty));
+
+            assert_eq!(contract.envelope_claim_of(id, accounts.bob), Some(33));
+            assert_eq!(contract.envelope_claim_of(id, accounts.eve), None);
+            assert_eq!(contract.total_supply(), 1_000_000);
+        }
+
+        // Test random red envelope and refund after expiry
+        #[ink::test]
+        fn test_envelope_random_split_and_refund() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let secret = b"hongbao".to_vec();
+            let secret_hash = sha2_256(&secret);
+            let id = contract.create_envelope(1_000, 3, SplitMode::Random, secret_hash, 1_000).unwrap();
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let bob_share = contract.claim_envelope(id, secret.clone()).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            let charlie_share = contract.claim_envelope(id, secret.clone()).unwrap();
+            // Every remaining claimer can still get a share
+            assert!(bob_share >= 1 && charlie_share >= 1);
+            assert!(bob_share + charlie_share < 1_000);
+
+            // Only the creator can refund, once expired
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.refund_envelope(id), Err(PSP22Error::EnvelopeNotExpired));
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_001);
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert_eq!(contract.claim_envelope(id, secret), Err(PSP22Error::EnvelopeExpired));
+            assert_eq!(contract.refund_envelope(id), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.refund_envelope(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - bob_share - charlie_share);
+            assert_eq!(contract.refund_envelope(id), Err(PSP22Error::EnvelopeEmpty));
+        }
+
+        // Test subscription collected once per period
+        #[ink::test]
+        fn test_subscription_collect() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let month = 30 * 24 * 60 * 60 * 1_000;
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let plan_id = contract.register_plan(100, month).unwrap();
+            assert_eq!(contract.plans_of(accounts.bob).len(), 1);
+
+            // The first period is paid on subscription
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            let id = contract.subscribe(plan_id).unwrap();
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+            assert_eq!(contract.subscriptions_of(accounts.alice)[0].id, id);
+            assert_eq!(contract.subscriptions_of_plan(plan_id).len(), 1);
+
+            // A keeper cannot collect before the next period
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.collect(id), Err(PSP22Error::PaymentNotDue));
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(month);
+            assert!(contract.collect(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 200);
+            assert_eq!(contract.collect(id), Err(PSP22Error::PaymentNotDue));
+
+            // Missed periods are not charged back
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(5 * month);
+            assert!(contract.collect(id).is_ok());
+            assert_eq!(contract.collect(id), Err(PSP22Error::PaymentNotDue));
+            assert_eq!(contract.balance_of(accounts.bob), 300);
+            assert_eq!(contract.get_subscription(id).unwrap().next_payment_at, 6 * month);
+
+            // The merchant can cancel
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert_eq!(contract.cancel_subscription(id), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.cancel_subscription(id).is_ok());
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(6 * month);
+            assert_eq!(contract.collect(id), Err(PSP22Error::SubscriptionNotActive));
+        }
+
+        // Test subscription lapsing on insufficient balance
+        #[ink::test]
+        fn test_subscription_lapse() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let plan_id = contract.register_plan(100, 1_000).unwrap();
+            assert_eq!(contract.register_plan(100, 0), Err(PSP22Error::InvalidPlan));
+
+            // Charlie can afford a single period
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.transfer(accounts.charlie, 150).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            let id = contract.subscribe(plan_id).unwrap();
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_000);
+            assert!(contract.collect(id).is_ok());
+            assert_eq!(contract.get_subscription(id).unwrap().status, SubscriptionStatus::Lapsed);
+            assert_eq!(contract.balance_of(accounts.charlie), 50);
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+            assert_eq!(contract.collect(id), Err(PSP22Error::SubscriptionNotActive));
+        }
+
+        // Test held tokens do not count towards a subscription payment
+        #[ink::test]
+        fn test_subscription_lapse_on_hold() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.set_hold_manager(accounts.django, true).is_ok());
+            assert!(contract.transfer(accounts.charlie, 250).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            let plan_id = contract.register_plan(100, 1_000).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            let id = contract.subscribe(plan_id).unwrap();
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert!(contract.hold(accounts.charlie, 60, 1).is_ok());
+
+            // After the first period, 150 with 60 held leaves 90 spendable, short of the 100 due
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_000);
+            assert!(contract.collect(id).is_ok());
+            assert_eq!(contract.get_subscription(id).unwrap().status, SubscriptionStatus::Lapsed);
+            assert_eq!(contract.balance_of(accounts.charlie), 150);
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+        }
+
+        // Test stream withdrawals while accruing
+        #[ink::test]
+        fn test_stream_withdraw() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // The deposit must match the rate and duration
+            assert_eq!(contract.create_stream(accounts.bob, 2, 999, 100, 600), Err(PSP22Error::InvalidStream));
+            let id = contract.create_stream(accounts.bob, 2, 1_000, 100, 600).unwrap();
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 1_000);
+            assert_eq!(contract.streams_of(accounts.bob).len(), 1);
+
+            // Nothing accrued before the start
+            assert_eq!(contract.withdrawable_from_stream(id), 0);
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(350);
+            assert_eq!(contract.withdrawable_from_stream(id), 500);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.withdraw_from_stream(id, 501), Err(PSP22Error::InsufficientBalance));
+            assert!(contract.withdraw_from_stream(id, 300).is_ok());
+            // Withdrawn funds cannot be withdrawn twice
+            assert_eq!(contract.withdrawable_from_stream(id), 200);
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(10_000);
+            assert_eq!(contract.withdrawable_from_stream(id), 700);
+            assert!(contract.withdraw_from_stream(id, 700).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 1_000);
+            assert_eq!(contract.withdrawable_from_stream(id), 0);
+        }
+
+        // Test stream cancellation split
+        #[ink::test]
+        fn test_stream_cancel() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let id = contract.create_stream(accounts.bob, 1, 1_000, 0, 1_000).unwrap();
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(400);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.withdraw_from_stream(id, 100).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.cancel_stream(id), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.cancel_stream(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 400);
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 400);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.withdraw_from_stream(id, 1), Err(PSP22Error::StreamNotActive));
+            assert_eq!(contract.cancel_stream(id), Err(PSP22Error::StreamNotActive));
+        }
+
+        // Test HTLC redeem with SHA-256 and Keccak-256 hashlocks
+        #[ink::test]
+        fn test_htlc_redeem() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let preimage = b"swap secret".to_vec();
+            let sha_id = contract.lock(accounts.bob, 100, Hashlock::Sha2x256(sha2_256(&preimage)), 1_000).unwrap();
+            let mut keccak_hash = [0u8; 32];
+            ink::env::hash_bytes::<Keccak256>(&preimage, &mut keccak_hash);
+            let keccak_id = contract.lock(accounts.bob, 50, Hashlock::Keccak256(keccak_hash), 1_000).unwrap();
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000 - 150);
+
+            // Anyone holding the preimage can redeem for the recipient
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.redeem(sha_id, b"guess".to_vec()), Err(PSP22Error::InvalidPreimage));
+            assert!(contract.redeem(sha_id, preimage.clone()).is_ok());
+            assert!(contract.redeem(keccak_id, preimage.clone()).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 150);
+            assert_eq!(contract.redeem(sha_id, preimage), Err(PSP22Error::HtlcNotLocked));
+            assert_eq!(contract.refund(sha_id), Err(PSP22Error::HtlcNotLocked));
+        }
+
+        // Test HTLC refund after the timelock
+        #[ink::test]
+        fn test_htlc_refund() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let preimage = b"swap secret".to_vec();
+            let id = contract.lock(accounts.bob, 100, Hashlock::Sha2x256(sha2_256(&preimage)), 1_000).unwrap();
+            assert_eq!(contract.refund(id), Err(PSP22Error::HtlcNotExpired));
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_000);
+            assert_eq!(contract.redeem(id, preimage), Err(PSP22Error::HtlcExpired));
+            assert!(contract.refund(id).is_ok());
+            assert_eq!(contract.balance_of(accounts.alice), 1_000_000);
+            assert_eq!(contract.get_htlc(id).unwrap().status, HtlcStatus::Refunded);
+        }
+
+        // Test admin messages are only callable by the new owner once ownership was handed
+        // over. The timelock cannot dispatch calls off-chain, so its address only stands in for
+        // it here, the full flow is covered by the timelock end-to-end tests.
+        #[ink::test]
+        fn test_admin_through_timelock() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let timelock = AccountId::from([0xCC; 32]);
+
+            assert_eq!(contract.owner(), Some(accounts.alice));
+            assert!(contract.mint(accounts.bob, 100).is_ok());
+            assert_eq!(contract.total_supply(), 1_000_000 + 100);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.transfer_ownership(accounts.bob), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.transfer_ownership(timelock).is_ok());
+            set_caller::<DefaultEnvironment>(timelock);
+            assert!(contract.accept_ownership().is_ok());
+
+            // The previous owner lost admin rights
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.mint(accounts.alice, 100), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(timelock);
+            assert!(contract.mint(accounts.bob, 100).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 200);
+            assert_eq!(contract.mint(accounts.bob, Balance::MAX), Err(PSP22Error::Overflow));
+        }
+
+        // Test two-step ownership transfer
+        #[ink::test]
+        fn test_ownership_transfer() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // A mistyped owner can be replaced before it accepts
+            assert!(contract.transfer_ownership(accounts.django).is_ok());
+            assert!(contract.transfer_ownership(accounts.bob).is_ok());
+            assert_eq!(contract.owner(), Some(accounts.alice));
+            assert_eq!(contract.pending_owner(), Some(accounts.bob));
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let event = <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap();
+            assert!(matches!(
+                event,
+                Event::OwnershipTransferStarted(OwnershipTransferStarted { previous_owner, new_owner })
+                    if previous_owner == Some(accounts.alice) && new_owner == accounts.bob
+            ));
+
+            set_caller::<DefaultEnvironment>(accounts.django);
+            assert_eq!(contract.accept_ownership(), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.accept_ownership().is_ok());
+            assert_eq!(contract.owner(), Some(accounts.bob));
+            assert_eq!(contract.pending_owner(), None);
+        }
+
+        // Test renouncing ownership disables admin messages
+        #[ink::test]
+        fn test_renounce_ownership() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            assert!(contract.transfer_ownership(accounts.bob).is_ok());
+            assert!(contract.renounce_ownership().is_ok());
+            assert_eq!(contract.owner(), None);
+
+            // The pending transfer is dropped as well
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.accept_ownership(), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.mint(accounts.alice, 1), Err(PSP22Error::NotOwner));
+            assert_eq!(contract.renounce_ownership(), Err(PSP22Error::NotOwner));
+        }
+
+        // Test upgrade is admin only and new deployments cannot be migrated
+        #[ink::test]
+        fn test_upgrade_admin_only() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.upgrade([1; 32]), Err(PSP22Error::NotOwner));
+            assert_eq!(contract.storage_version(), STORAGE_VERSION);
+            assert_eq!(contract.migrate(accounts.bob), Err(PSP22Error::AlreadyMigrated));
+            assert_eq!(contract.owner(), Some(accounts.alice));
+        }
+
+        // Test the holder set follows balances in and out of zero
+        #[ink::test]
+        fn test_holders() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert_eq!(contract.holder_count(), 1);
+
+            assert!(contract.transfer(accounts.bob, 100).is_ok());
+            assert!(contract.transfer(accounts.charlie, 200).is_ok());
+            assert_eq!(contract.holder_count(), 3);
+            assert_eq!(contract.holders(1, 10), ink::prelude::vec![(accounts.bob, 100), (accounts.charlie, 200)]);
+
+            // Bob empties the balance, Charlie takes over the index
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer(accounts.alice, 100).is_ok());
+            assert_eq!(contract.holder_count(), 2);
+            assert_eq!(contract.balances.get(accounts.bob), None);
+            assert_eq!(contract.holders(0, 10), ink::prelude::vec![(accounts.alice, 999_800), (accounts.charlie, 200)]);
+            assert_eq!(contract.holders(1, 1), ink::prelude::vec![(accounts.charlie, 200)]);
+            assert_eq!(contract.holders(5, 1), Vec::new());
+        }
+
+        // Test expired approvals count as zero
+        #[ink::test]
+        fn test_allowance_expiry() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_000);
+            assert_eq!(contract.approve_with_expiry(accounts.bob, 100, 1_000), Err(PSP22Error::InvalidExpiry));
+            assert!(contract.approve_with_expiry(accounts.bob, 100, 2_000).is_ok());
+            assert!(contract.increase_allowance(accounts.bob, 50).is_ok());
+            assert_eq!(
+                contract.allowance_with_expiry(accounts.alice, accounts.bob),
+                Allowance { amount: 150, expires_at: Some(2_000) }
+            );
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(2_000);
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 0);
+            assert_eq!(contract.allowance_with_expiry(accounts.alice, accounts.bob).amount, 150);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                contract.transfer_from(accounts.alice, accounts.bob, 50),
+                Err(PSP22Error::AllowanceExpired)
+            );
+
+            // Approvals without expiry never expire
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.approve(accounts.bob, 100).is_ok());
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(u64::MAX);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer_from(accounts.alice, accounts.bob, 50).is_ok());
+        }
+
+        // Test the approve race: the spender front-runs a change of a non-zero allowance
+        #[ink::test]
+        fn test_approve_race() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.approve(accounts.bob, 100).is_ok());
+
+            // Alice lowers the allowance to 50, Bob spends the 100 first
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer_from(accounts.alice, accounts.bob, 100).is_ok());
+            // A plain approve then lets Bob spend the new 50 as well
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.approve(accounts.bob, 50).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer_from(accounts.alice, accounts.bob, 50).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 150);
+        }
+
+        // Test compare-and-set approve rejects a front-run change
+        #[ink::test]
+        fn test_approve_if() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.approve_if(accounts.bob, 0, 100).is_ok());
+
+            // Bob front-runs the change from 100 to 50
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer_from(accounts.alice, accounts.bob, 100).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.approve_if(accounts.bob, 100, 50), Err(PSP22Error::AllowanceChanged));
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 0);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                contract.transfer_from(accounts.alice, accounts.bob, 50),
+                Err(PSP22Error::InsufficientAllowance)
+            );
+            assert_eq!(contract.balance_of(accounts.bob), 100);
+
+            // Without interference the change goes through
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.approve(accounts.charlie, 100).is_ok());
+            assert!(contract.approve_if(accounts.charlie, 100, 50).is_ok());
+            assert_eq!(contract.allowance(accounts.alice, accounts.charlie), 50);
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let event = <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap();
+            assert!(matches!(
+                event,
+                Event::Approval(Approval { owner, spender, value })
+                    if owner == accounts.alice && spender == accounts.charlie && value == 50
+            ));
+
+            // The expiry of the current allowance is kept
+            assert!(contract.approve_with_expiry(accounts.django, 100, 2_000).is_ok());
+            assert!(contract.approve_if(accounts.django, 100, 70).is_ok());
+            assert_eq!(
+                contract.allowance_with_expiry(accounts.alice, accounts.django),
+                Allowance { amount: 70, expires_at: Some(2_000) }
+            );
+        }
+
+        // Test strict mode requires resetting an allowance to zero first
+        #[ink::test]
+        fn test_strict_approvals() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            contract.set_strict_approvals(true);
+            assert!(contract.strict_approvals(accounts.alice));
+            assert!(contract.approve(accounts.bob, 100).is_ok());
+            assert_eq!(contract.approve(accounts.bob, 50), Err(PSP22Error::AllowanceNotZero));
+            assert!(contract.approve(accounts.bob, 0).is_ok());
+            assert!(contract.approve(accounts.bob, 50).is_ok());
+
+            contract.set_strict_approvals(false);
+            assert!(contract.approve(accounts.bob, 70).is_ok());
+        }
+
+        // Test the admin-configured flash loan fee and limits
+        #[ink::test]
+        fn test_flash_fee() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            assert_eq!(contract.flash_fee(1_000_000), 0);
+            assert!(contract.set_flash_fee_bps(9).is_ok());
+            assert_eq!(contract.flash_fee(1_000_000), 900);
+            assert_eq!(contract.flash_fee(1_111), 0);
+            assert_eq!(contract.flash_fee(Balance::MAX), Balance::MAX / 10_000 * 9 + 1);
+            assert_eq!(contract.set_flash_fee_bps(10_001), Err(PSP22Error::InvalidFlashFee));
+
+            assert_eq!(contract.max_flashloan(), Balance::MAX - 1_000_000);
+            assert_eq!(
+                contract.flashloan(accounts.bob, Balance::MAX, Vec::new()),
+                Err(PSP22Error::FlashloanTooLarge)
+            );
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.set_flash_fee_bps(0), Err(PSP22Error::NotOwner));
+            assert_eq!(contract.flash_fee_bps(), 9);
+        }
+
+        // Test the repayment taken once `on_flashloan` returned. The hook itself is a
+        // cross-contract call, so the loan and the approval it makes are done directly here.
+        #[ink::test]
+        fn test_flashloan_repayment() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let this = AccountId::from([0xFF; 32]);
+            assert!(contract.set_flash_fee_bps(9).is_ok());
+            let fee = contract.flash_fee(1_000_000);
+            assert!(contract.transfer(accounts.bob, fee).is_ok());
+
+            // The borrower approves the loan and the fee, paid out of its own balance
+            assert!(contract.mint_to(accounts.bob, 1_000_000).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.approve(this, 1_000_000 + fee).is_ok());
+            assert!(contract.repay_flashloan(accounts.bob, 1_000_000 + fee).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 0);
+            assert_eq!(contract.allowance(accounts.bob, this), 0);
+            assert_eq!(contract.total_supply(), 1_000_000 - fee);
+
+            // A borrower approving less than the loan and the fee
+            assert!(contract.mint_to(accounts.bob, 1_000_000).is_ok());
+            assert!(contract.approve(this, 1_000_000).is_ok());
+            assert_eq!(contract.repay_flashloan(accounts.bob, 1_000_000 + fee), Err(PSP22Error::InsufficientAllowance));
+            assert_eq!(contract.balance_of(accounts.bob), 1_000_000);
+
+            // A borrower unable to pay the fee
+            assert!(contract.approve(this, 1_000_000 + fee).is_ok());
+            assert_eq!(contract.repay_flashloan(accounts.bob, 1_000_000 + fee), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(contract.balance_of(accounts.bob), 1_000_000);
+        }
+
+        // Test rewards are shared by stake and time between stakers entering at different times
+        #[ink::test]
+        fn test_staking_rewards() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.transfer(accounts.bob, 10_000).is_ok());
+            assert!(contract.fund_rewards(1_000).is_ok());
+            assert!(contract.set_reward_rate(10).is_ok());
+
+            assert!(contract.stake(1_000).is_ok());
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(10_000);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.stake(3_000).is_ok());
+            assert_eq!(contract.earned(accounts.alice), Ok(100));
+            assert_eq!(contract.earned(accounts.bob), Ok(0));
+
+            // 10 tokens per second shared 1:3
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(20_000);
+            assert_eq!(contract.earned(accounts.alice), Ok(125));
+            assert_eq!(contract.earned(accounts.bob), Ok(75));
+            assert_eq!(contract.claim_rewards(), Ok(75));
+            assert_eq!(contract.balance_of(accounts.bob), 7_075);
+            assert!(contract.unstake(3_000).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 10_075);
+
+            // Alice earns everything once alone
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(30_000);
+            assert_eq!(contract.earned(accounts.alice), Ok(225));
+            assert_eq!(contract.earned(accounts.bob), Ok(0));
+            assert_eq!(contract.unstake(1), Err(PSP22Error::InsufficientStake));
+
+            // Accrual stops once the funded rewards are distributed
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(200_000);
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.earned(accounts.alice), Ok(925));
+            assert_eq!(contract.claim_rewards(), Ok(925));
+            assert_eq!(contract.staking_pool().reward_pool, 0);
+        }
+
+        // Test rewards accrue only while the pool is funded, and never retroactively
+        #[ink::test]
+        fn test_staking_unfunded_pool() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.set_reward_rate(10).is_ok());
+            assert!(contract.stake(3_000).is_ok());
+
+            // Nothing accrues from an empty pool
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(10_000);
+            assert_eq!(contract.earned(accounts.alice), Ok(0));
+            assert_eq!(contract.claim_rewards(), Ok(0));
+
+            // Funding only pays for the time after it
+            assert!(contract.fund_rewards(50).is_ok());
+            assert_eq!(contract.earned(accounts.alice), Ok(0));
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(13_000);
+            assert_eq!(contract.earned(accounts.alice), Ok(30));
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(60_000);
+            assert_eq!(contract.earned(accounts.alice), Ok(49));
+
+            // Rounding dust is left escrowed rather than owed beyond the funds
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(60_000);
+            assert!(contract.unstake(1_000).is_ok());
+            assert!(contract.fund_rewards(1).is_ok());
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(70_000);
+            assert_eq!(contract.claim_rewards(), Ok(50));
+            assert_eq!(contract.staking_pool().reward_pool, 0);
+            assert_eq!(contract.escrowed(), 2_001);
+        }
+
+        // Test reward arithmetic reports overflow instead of capping rewards
+        #[ink::test]
+        fn test_staking_overflow() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.fund_rewards(1_000).is_ok());
+            assert!(contract.set_reward_rate(Balance::MAX / 1_000).is_ok());
+            assert!(contract.stake(1_000).is_ok());
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(2_000);
+            assert_eq!(contract.earned(accounts.alice), Err(PSP22Error::Overflow));
+            assert_eq!(contract.claim_rewards(), Err(PSP22Error::Overflow));
+            assert_eq!(contract.unstake(1_000), Err(PSP22Error::Overflow));
+        }
+
+        // Test unstaked tokens are held until the unbonding period ends
+        #[ink::test]
+        fn test_unbonding() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.set_unbonding_period(5_000).is_ok());
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let event = <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap();
+            assert!(matches!(
+                event,
+                Event::UnbondingPeriodChanged(UnbondingPeriodChanged { old: 0, new: 5_000 })
+            ));
+            assert!(contract.stake(1_000).is_ok());
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(1_000);
+            assert!(contract.unstake(400).is_ok());
+            assert_eq!(contract.get_stake(accounts.alice).unbonding, 400);
+            assert_eq!(contract.balance_of(accounts.alice), 999_000);
+            assert_eq!(contract.withdraw_unbonded(), Err(PSP22Error::UnbondingNotDue));
+
+            ink::env::test::set_block_timestamp::<DefaultEnvironment>(6_000);
+            assert!(contract.withdraw_unbonded().is_ok());
+            assert_eq!(contract.balance_of(accounts.alice), 999_400);
+            let event = ink::env::test::recorded_events().last().unwrap();
+            let event = <Event as scale::Decode>::decode(&mut &event.data[..]).unwrap();
+            assert!(matches!(
+                event,
+                Event::Withdrawn(Withdrawn { account, amount: 400 }) if account == accounts.alice
+            ));
+            assert_eq!(contract.withdraw_unbonded(), Err(PSP22Error::InsufficientStake));
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.set_unbonding_period(0), Err(PSP22Error::NotOwner));
+        }
+
+        // Test held tokens stay in the balance but cannot be spent
+        #[ink::test]
+        fn test_hold_balances() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.transfer(accounts.bob, 1_000).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.hold(accounts.bob, 100, 1), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(contract.set_hold_manager(accounts.charlie, true).is_ok());
+
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert!(contract.hold(accounts.bob, 600, 1).is_ok());
+            assert!(contract.hold(accounts.bob, 300, 2).is_ok());
+            assert_eq!(contract.hold(accounts.bob, 101, 3), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(contract.balance_of(accounts.bob), 1_000);
+            assert_eq!(contract.held_balance_of(accounts.bob), 900);
+            assert_eq!(contract.spendable_balance_of(accounts.bob), 100);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.transfer(accounts.alice, 101), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(contract.burn(accounts.bob, 101), Err(PSP22Error::InsufficientBalance));
+            assert!(contract.transfer(accounts.alice, 100).is_ok());
+
+            // Holds are released per manager and reason
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert_eq!(contract.release_hold(accounts.bob, 301, 2), Err(PSP22Error::InsufficientHold));
+            assert!(contract.release_hold(accounts.bob, 300, 2).is_ok());
+            assert_eq!(contract.hold_of(accounts.bob, accounts.charlie, 2), 0);
+            assert_eq!(contract.spendable_balance_of(accounts.bob), 300);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.transfer(accounts.alice, 300).is_ok());
+            assert_eq!(contract.balance_of(accounts.bob), 600);
+        }
+
+        // Test a token deployed on behalf of another account
+        #[ink::test]
+        fn test_for_owner() {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_callee::<DefaultEnvironment>(AccountId::from([0xFF; 32]));
+            let mut contract = PidChatPSP22::for_owner(accounts.bob, 500, None, None, 12);
+
+            assert_eq!(contract.balance_of(accounts.bob), 500);
+            assert_eq!(contract.balance_of(accounts.alice), 0);
+            assert_eq!(contract.owner(), Some(accounts.bob));
+            assert_eq!(contract.mint(accounts.alice, 1), Err(PSP22Error::NotOwner));
+        }
+
+        // Test PID sent to the contract is recovered without touching escrowed tokens
+        #[ink::test]
+        fn test_recover_self_balance() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let this = AccountId::from([0xFF; 32]);
+
+            assert!(contract.stake(1_000).is_ok());
+            assert!(contract.send_tip(accounts.bob, 200, Vec::new(), 1_000).is_ok());
+            assert_eq!(contract.recover_self_balance(accounts.charlie), Ok(0));
+
+            // A user mistakenly sends PID to the contract address
+            assert!(contract.transfer(this, 300).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.recover_self_balance(accounts.bob), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert_eq!(contract.recover_self_balance(accounts.charlie), Ok(300));
+            assert_eq!(contract.balance_of(accounts.charlie), 300);
+            assert_eq!(contract.balance_of(this), 1_200);
+
+            // Escrowed tokens are still paid out
+            assert!(contract.unstake(1_000).is_ok());
+            assert_eq!(contract.balance_of(this), 200);
+        }
+
+        // Test native funds and foreign tokens are rescued by the admin only
+        #[ink::test]
+        fn test_rescue() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            let this = AccountId::from([0xFF; 32]);
+            ink::env::test::set_account_balance::<DefaultEnvironment>(this, 1_000);
+            ink::env::test::set_account_balance::<DefaultEnvironment>(accounts.charlie, 0);
+
+            assert!(contract.rescue_native(accounts.charlie, 400).is_ok());
+            assert_eq!(ink::env::test::get_account_balance::<DefaultEnvironment>(accounts.charlie), Ok(400));
+            assert_eq!(ink::env::test::get_account_balance::<DefaultEnvironment>(this), Ok(600));
+
+            // PID itself goes through `recover_self_balance`
+            assert_eq!(contract.rescue_psp22(this, accounts.charlie, 1), Err(PSP22Error::Unauthorized));
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.rescue_native(accounts.bob, 1), Err(PSP22Error::NotOwner));
+            assert_eq!(
+                contract.rescue_psp22(AccountId::from([0xBB; 32]), accounts.bob, 1),
+                Err(PSP22Error::NotOwner)
+            );
+        }
+
+        // Test spenders are listed per owner and revoked at once
+        #[ink::test]
+        fn test_revoke_all_allowances() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            assert!(contract.approve(accounts.bob, 100).is_ok());
+            assert!(contract.approve(accounts.charlie, 200).is_ok());
+            assert!(contract.approve(accounts.django, 300).is_ok());
+            // A zero allowance leaves the set, Django takes over Bob's index
+            assert!(contract.approve(accounts.bob, 0).is_ok());
+            assert_eq!(
+                contract.spenders_of(accounts.alice, 0, 10),
+                ink::prelude::vec![(accounts.django, 300), (accounts.charlie, 200)]
+            );
+            assert_eq!(contract.spenders_of(accounts.bob, 0, 10), Vec::new());
+
+            let events_before = ink::env::test::recorded_events().count();
+            assert_eq!(contract.revoke_all_allowances(), 0);
+            assert_eq!(ink::env::test::recorded_events().count(), events_before + 2);
+            assert_eq!(contract.spenders_of(accounts.alice, 0, 10), Vec::new());
+            assert_eq!(contract.allowance(accounts.alice, accounts.charlie), 0);
+            assert_eq!(contract.allowances.get((accounts.alice, accounts.django)), None);
+        }
+
+        // Test the number of spenders is not capped and revoking them is paged
+        #[ink::test]
+        fn test_revoke_all_allowances_paged() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let spenders = MAX_SPENDERS_PAGE + 5;
+            for seed in 0..spenders {
+                let spender = AccountId::from([u8::try_from(seed).unwrap(); 32]);
+                assert!(contract.approve(spender, 1).is_ok());
+            }
+            assert_eq!(contract.spender_count.get(accounts.alice), Some(spenders));
+
+            assert_eq!(contract.revoke_all_allowances(), 5);
+            assert_eq!(contract.spenders_of(accounts.alice, 0, MAX_SPENDERS_PAGE).len(), 5);
+            assert_eq!(contract.allowance(accounts.alice, AccountId::from([0; 32])), 1);
+            assert_eq!(contract.revoke_all_allowances(), 0);
+            assert_eq!(contract.allowance(accounts.alice, AccountId::from([0; 32])), 0);
+            assert_eq!(contract.revoke_all_allowances(), 0);
+        }
+
+        // Test metadata updates are admin only and reflected by the queries
+        #[ink::test]
+        fn test_update_metadata() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            assert!(contract.set_name(String::from("PidChat Token")).is_ok());
+            assert!(contract.set_symbol(String::from("PIDC")).is_ok());
+            assert!(contract.set_metadata_uri(String::from("ipfs://logo")).is_ok());
+            assert!(contract.set_description(String::from("Chat payments")).is_ok());
+            assert_eq!(contract.token_name(), b"PidChat Token".to_vec());
+            assert_eq!(contract.token_uri(), String::from("ipfs://logo"));
+            assert_eq!(
+                contract.metadata(),
+                TokenMetadata {
+                    name: String::from("PidChat Token"),
+                    symbol: String::from("PIDC"),
+                    decimals: 18,
+                    uri: String::from("ipfs://logo"),
+                    description: String::from("Chat payments"),
+                }
+            );
+            assert_eq!(contract.set_description("x".repeat(1_025)), Err(PSP22Error::MetadataTooLong));
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(contract.set_name(String::from("Bob Token")), Err(PSP22Error::NotOwner));
+            assert_eq!(contract.metadata().name, String::from("PidChat Token"));
+        }
+
+        // Test metadata and supply are stored under their fixed keys
+        #[ink::test]
+        fn test_storage_keys() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.mint(accounts.bob, 5).is_ok());
+
+            let name = ink::env::get_contract_storage::<u32, String>(&NAME_KEY).unwrap();
+            assert_eq!(name, Some(String::from("TestToken")));
+            let total_supply = ink::env::get_contract_storage::<u32, Balance>(&TOTAL_SUPPLY_KEY).unwrap();
+            assert_eq!(total_supply, Some(1_000_005));
+        }
+
+        // Storage layout of the first release, whose mapping keys ink derives from the
+        // struct and field names
+        mod first_release {
+            use ink::prelude::string::String;
+            use ink::prelude::vec::Vec;
+            use ink::primitives::AccountId;
+            use ink::storage::Mapping;
+            use super::LegacyTransfer;
+
+            #[ink::storage_item]
+            #[derive(Default)]
+            pub struct PidChatPSP22 {
+                pub name: Option<String>,
+                pub symbol: Option<String>,
+                pub decimals: u8,
+                pub allowances: Mapping<(AccountId, AccountId), u128>,
+                pub balances: Mapping<AccountId, u128>,
+                pub total_supply: u128,
+                pub transfers: Mapping<AccountId, Vec<LegacyTransfer>>,
+            }
+        }
+
+        // Helper function to load a first release deployment from storage as written by the
+        // first release code: Alice holds 900 and Charlie 100 after one transfer, Bob has a
+        // zero balance left in storage and two allowances of Alice are set
+        fn setup_v1() -> PidChatPSP22 {
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_callee::<DefaultEnvironment>(AccountId::from([0xFF; 32]));
+            let root_key = <PidChatPSP22 as ink::storage::traits::StorageKey>::KEY;
+
+            let mut v1 = first_release::PidChatPSP22 {
+                name: Some(String::from("PidChat")),
+                symbol: Some(String::from("PID")),
+                decimals: 18,
+                total_supply: 1_000,
+                ..Default::default()
+            };
+            v1.balances.insert(accounts.alice, &900);
+            v1.balances.insert(accounts.charlie, &100);
+            v1.balances.insert(accounts.bob, &0);
+            v1.allowances.insert((accounts.alice, accounts.bob), &100);
+            v1.allowances.insert((accounts.alice, accounts.charlie), &200);
+            let transfer = (accounts.alice, accounts.charlie, 100, 7);
+            v1.transfers.insert(accounts.alice, &vec![transfer]);
+            v1.transfers.insert(accounts.charlie, &vec![transfer]);
+            ink::env::set_contract_storage(&root_key, &v1);
+            ink::env::get_contract_storage::<_, PidChatPSP22>(&root_key).unwrap().unwrap()
+        }
+
+        // Test migration of a first release deployment
+        #[ink::test]
+        fn test_migrate_from_v1() {
+            let mut contract = setup_v1();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // Balances and allowances are read in place
+            assert_eq!(contract.storage_version(), 1);
+            assert_eq!(contract.owner(), None);
+            assert_eq!(contract.balance_of(accounts.alice), 900);
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 100);
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(contract.migrate(accounts.alice).is_ok());
+            assert_eq!(contract.migrate(accounts.bob), Err(PSP22Error::AlreadyMigrated));
+            assert_eq!(contract.storage_version(), STORAGE_VERSION);
+            assert_eq!(contract.owner(), Some(accounts.alice));
+            assert_eq!(contract.legacy, LegacyRoot::default());
+            assert_eq!(contract.token_name(), b"PidChat".to_vec());
+            assert_eq!(contract.token_decimals(), 18);
+            assert_eq!(contract.total_supply(), 1_000);
+
+            // Only the admin runs the batches, which are bounded
+            assert_eq!(contract.migrate_accounts(Vec::new()), Err(PSP22Error::NotOwner));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            let too_many = vec![(accounts.alice, accounts.bob); MAX_MIGRATION_BATCH + 1];
+            assert_eq!(contract.migrate_allowances(too_many), Err(PSP22Error::BatchTooLarge));
+
+            let batch = vec![accounts.bob, accounts.charlie, accounts.django];
+            assert!(contract.migrate_accounts(batch.clone()).is_ok());
+            // Batches can be repeated
+            assert!(contract.migrate_accounts(batch).is_ok());
+            assert_eq!(contract.holders(0, 10), vec![(accounts.charlie, 100)]);
+            assert_eq!(contract.balances.get(accounts.bob), None);
+
+            // Accounts touched since the migration are indexed by the transfer
+            assert!(contract.transfer(accounts.charlie, 10).is_ok());
+            assert_eq!(contract.holders(0, 10), vec![(accounts.charlie, 110), (accounts.alice, 890)]);
+            assert!(contract.migrate_accounts(vec![accounts.alice]).is_ok());
+            assert_eq!(contract.holder_count(), 2);
+
+            // Histories are converted ahead of the entries recorded since
+            let history = contract.read_history(accounts.alice);
+            assert_eq!(history.len(), 2);
+            assert_eq!((history[0].id, history[0].value, history[0].timestamp), (0, 100, 7));
+            assert_eq!((history[1].id, history[1].value), (contract.last_transfer_id(), 10));
+            assert_eq!(contract.legacy_transfers.get(accounts.alice), None);
+            // Converted before the transfer, the entry recorded since follows as well
+            let history = contract.read_history(accounts.charlie);
+            assert_eq!(history.iter().map(|record| record.id).collect::<Vec<_>>(), [0, contract.last_transfer_id()]);
+
+            let pairs = vec![(accounts.alice, accounts.bob), (accounts.bob, accounts.django)];
+            assert_eq!(contract.migrate_allowances(pairs.clone()), Ok(()));
+            assert_eq!(contract.migrate_allowances(pairs), Ok(()));
+            assert_eq!(contract.spenders_of(accounts.alice, 0, 10), vec![(accounts.bob, 100)]);
+            assert_eq!(contract.spenders_of(accounts.bob, 0, 10), Vec::new());
+            // Pairs missed by the batches keep their allowance
+            assert_eq!(contract.allowance(accounts.alice, accounts.charlie), 200);
+        }
+
+        // Test revoking allowances given before the migration
+        #[ink::test]
+        fn test_revoke_allowances_from_v1() {
+            let mut contract = setup_v1();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert!(contract.migrate(accounts.alice).is_ok());
+
+            // Not indexed yet, so neither listed nor revoked
+            assert_eq!(contract.spenders_of(accounts.alice, 0, 10), Vec::new());
+            assert_eq!(contract.revoke_all_allowances(), 0);
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 100);
+
+            // The migration batch indexes the spender
+            assert_eq!(contract.migrate_allowances(vec![(accounts.alice, accounts.bob)]), Ok(()));
+            assert_eq!(contract.spenders_of(accounts.alice, 0, 10), vec![(accounts.bob, 100)]);
+            assert!(contract.approve(accounts.django, 50).is_ok());
+            assert_eq!(contract.revoke_all_allowances(), 0);
+            assert_eq!(contract.allowance(accounts.alice, accounts.bob), 0);
+            assert_eq!(contract.allowance(accounts.alice, accounts.django), 0);
+            assert_eq!(contract.spenders_of(accounts.alice, 0, 10), Vec::new());
+        }
+
+        // Test transfer log eviction at the shipped log size
+        #[ink::test]
+        fn test_transfer_log_bounded() {
+            let mut contract = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            // The mint is id 1, the log is full after MAX_TRANSFER_LOG_SIZE - 1 transfers
+            for _ in 0..MAX_TRANSFER_LOG_SIZE - 1 {
+                assert!(contract.transfer(accounts.bob, 1).is_ok());
+            }
+            assert_eq!(contract.transfers_since(0, 1)[0].id, 1);
+
+            // Each further transfer evicts the oldest entry
+            for _ in 0..3 {
+                assert!(contract.transfer(accounts.bob, 1).is_ok());
+            }
+            let last = contract.last_transfer_id();
+            assert_eq!(last, MAX_TRANSFER_LOG_SIZE + 3);
+            assert_eq!(contract.transfers_since(0, 1)[0].id, 4);
+            for id in 1..4 {
+                assert!(contract.transfer_log.get(id).is_none());
+            }
+            let tail = contract.transfers_since(last - 2, MAX_TRANSFER_LOG_PAGE);
+            assert_eq!(tail.iter().map(|record| record.id).collect::<Vec<_>>(), [last - 1, last]);
+        }
+    }
+
+    // Cross-contract calls cannot be dispatched off-chain, so rescuing foreign tokens is
+    // covered here. Needs a running substrate-contracts-node: `cargo test --features e2e-tests`.
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        // Helper function to deploy a token minted to Alice
+        async fn deploy(client: &mut ink_e2e::Client<C, E>, symbol: &str) -> AccountId {
+            let constructor = PidChatPSP22Ref::new(1_000_000, None, Some(symbol.into()), 18);
+            client
+                .instantiate("pidchat_psp22", &ink_e2e::alice(), constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id
+        }
+
+        // Helper function to read a token balance
+        async fn balance_of(client: &mut ink_e2e::Client<C, E>, token: AccountId, owner: AccountId) -> Balance {
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(owner));
+            client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value()
+        }
+
+        // Test a second PidChat token sent to the contract is rescued by the admin only. It
+        // does not dispatch the standard `PSP22::transfer`, so the rescue falls back to the
+        // `Psp22::transfer` message of this crate.
+        #[ink_e2e::test]
+        async fn e2e_rescue_psp22(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let token = deploy(&mut client, "PID").await;
+            let foreign = deploy(&mut client, "OTHER").await;
+
+            let send = build_message::<PidChatPSP22Ref>(foreign).call(|contract| contract.transfer(token, 500));
+            client.call(&ink_e2e::alice(), send, 0, None).await.expect("transfer failed");
+
+            let rescue = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.rescue_psp22(foreign, bob, 200));
+            let result = client.call_dry_run(&ink_e2e::bob(), &rescue, 0, None).await;
+            assert_eq!(result.return_value(), Err(PSP22Error::NotOwner));
+            let result = client.call(&ink_e2e::alice(), rescue, 0, None).await.expect("rescue_psp22 failed");
+            assert_eq!(result.return_value(), Ok(()));
+            assert_eq!(balance_of(&mut client, foreign, token).await, 300);
+            assert_eq!(balance_of(&mut client, foreign, bob).await, 200);
+
+            // More than the contract holds is refused by the foreign token
+            let rescue = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.rescue_psp22(foreign, bob, 301));
+            let result = client.call_dry_run(&ink_e2e::alice(), &rescue, 0, None).await;
+            assert!(result.return_value().is_err());
+            Ok(())
+        }
+    }
+}
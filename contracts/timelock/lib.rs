@@ -0,0 +1,436 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+use ink::env::Environment;
+use ink::prelude::vec::Vec;
+type DefaultAccountId = <ink::env::DefaultEnvironment as Environment>::AccountId;
+
+/// Lifecycle of a scheduled call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub enum OperationStatus {
+    Pending,
+    Executed,
+    Cancelled,
+}
+
+/// Call of `selector` on `target` with SCALE encoded `input`, executable from `eta`.
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+pub struct Operation {
+    pub id: u64,
+    pub target: DefaultAccountId,
+    pub selector: [u8; 4],
+    pub input: Vec<u8>,
+    pub eta: u64,
+    pub status: OperationStatus,
+}
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum TimelockError {
+    NotAdmin,
+    NotTimelock,
+    EtaTooEarly,
+    OperationNotFound,
+    OperationNotPending,
+    OperationNotReady,
+    CallFailed,
+}
+
+/// Raw call arguments, already SCALE encoded by the scheduler.
+struct CallInput<'a>(&'a [u8]);
+
+impl scale::Encode for CallInput<'_> {
+    fn encode_to<T: scale::Output + ?Sized>(&self, dest: &mut T) {
+        dest.write(self.0);
+    }
+}
+
+#[ink::contract]
+pub mod timelock {
+    use super::{
+        CallInput,
+        DefaultAccountId,
+        Operation,
+        OperationStatus,
+        TimelockError,
+    };
+    use ink::env::call::{
+        build_call,
+        ExecutionInput,
+        Selector,
+    };
+    use ink::env::CallFlags;
+    use ink::prelude::vec::Vec;
+    use ink_storage::{
+        Lazy,
+        Mapping,
+    };
+
+    /// Timelock controller: privileged calls are announced with an ETA at least `min_delay`
+    /// ahead, then executed or cancelled. Meant to own the token so that admin messages can
+    /// only go through it.
+    #[ink(storage)]
+    pub struct Timelock {
+        admin: DefaultAccountId,
+        // Kept out of the root cell: `update_delay` is reached through a re-entrant call from
+        // `execute`, whose root cell write-back would otherwise revert the new delay
+        min_delay: Lazy<u64>,
+        operations: Mapping<u64, Operation>,
+        last_operation_id: u64,
+    }
+
+    // Define the CallScheduled event
+    #[ink(event)]
+    pub struct CallScheduled {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        target: AccountId,
+        selector: [u8; 4],
+        input: Vec<u8>,
+        eta: u64,
+    }
+
+    // Define the CallExecuted event
+    #[ink(event)]
+    pub struct CallExecuted {
+        #[ink(topic)]
+        id: u64,
+    }
+
+    // Define the Cancelled event
+    #[ink(event)]
+    pub struct Cancelled {
+        #[ink(topic)]
+        id: u64,
+    }
+
+    // Define the MinDelayChanged event
+    #[ink(event)]
+    pub struct MinDelayChanged {
+        old_delay: u64,
+        new_delay: u64,
+    }
+
+    impl Timelock {
+        /// Creates a timelock where the caller schedules and cancels calls, with a minimum
+        /// delay of `min_delay` ms between scheduling and execution.
+        #[ink(constructor)]
+        pub fn new(min_delay: u64) -> Self {
+            let mut instance = Self {
+                admin: Self::env().caller(),
+                min_delay: Lazy::new(),
+                operations: Mapping::new(),
+                last_operation_id: 0,
+            };
+            instance.min_delay.set(&min_delay);
+            instance
+        }
+
+        /// Schedules a call of `selector` on `target` with the SCALE encoded `input`,
+        /// executable from `eta`. Returns the id of the operation.
+        #[ink(message)]
+        pub fn schedule(&mut self, target: AccountId, selector: [u8; 4], input: Vec<u8>, eta: u64) -> Result<u64, TimelockError> {
+            self.ensure_admin()?;
+            if eta < self.env().block_timestamp().saturating_add(self.min_delay()) {
+                return Err(TimelockError::EtaTooEarly);
+            }
+            let id = self.last_operation_id.saturating_add(1);
+            self.operations.insert(id, &Operation {
+                id,
+                target,
+                selector,
+                input: input.clone(),
+                eta,
+                status: OperationStatus::Pending,
+            });
+            self.last_operation_id = id;
+            self.env().emit_event(CallScheduled {
+                id,
+                target,
+                selector,
+                input,
+                eta,
+            });
+            Ok(id)
+        }
+
+        /// Executes a pending operation once its ETA is reached. Anyone can execute since the
+        /// call was already announced.
+        #[ink(message)]
+        pub fn execute(&mut self, id: u64) -> Result<(), TimelockError> {
+            let mut operation = self.operations.get(id).ok_or(TimelockError::OperationNotFound)?;
+            if operation.status != OperationStatus::Pending {
+                return Err(TimelockError::OperationNotPending);
+            }
+            if self.env().block_timestamp() < operation.eta {
+                return Err(TimelockError::OperationNotReady);
+            }
+            // Mark as executed first so a re-entrant call cannot execute it twice
+            operation.status = OperationStatus::Executed;
+            self.operations.insert(id, &operation);
+            // Calls to the timelock itself, e.g. `update_delay`, need re-entrancy
+            let call_flags = CallFlags::default().set_allow_reentry(operation.target == self.env().account_id());
+            build_call::<Environment>()
+                .call(operation.target)
+                .call_flags(call_flags)
+                .exec_input(ExecutionInput::new(Selector::new(operation.selector)).push_arg(CallInput(&operation.input)))
+                .returns::<()>()
+                .try_invoke()
+                .map_err(|_| TimelockError::CallFailed)?
+                .map_err(|_| TimelockError::CallFailed)?;
+            self.env().emit_event(CallExecuted { id });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn cancel(&mut self, id: u64) -> Result<(), TimelockError> {
+            self.ensure_admin()?;
+            let mut operation = self.operations.get(id).ok_or(TimelockError::OperationNotFound)?;
+            if operation.status != OperationStatus::Pending {
+                return Err(TimelockError::OperationNotPending);
+            }
+            operation.status = OperationStatus::Cancelled;
+            self.operations.insert(id, &operation);
+            self.env().emit_event(Cancelled { id });
+            Ok(())
+        }
+
+        /// Changes the minimum delay. Only callable by the timelock itself, so the change has
+        /// to be scheduled like any other privileged call.
+        #[ink(message)]
+        pub fn update_delay(&mut self, new_delay: u64) -> Result<(), TimelockError> {
+            if self.env().caller() != self.env().account_id() {
+                return Err(TimelockError::NotTimelock);
+            }
+            let old_delay = self.min_delay();
+            self.min_delay.set(&new_delay);
+            self.env().emit_event(MinDelayChanged { old_delay, new_delay });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn admin(&self) -> AccountId {
+            self.admin
+        }
+
+        #[ink(message)]
+        pub fn min_delay(&self) -> u64 {
+            self.min_delay.get().unwrap_or(0)
+        }
+
+        #[ink(message)]
+        pub fn get_operation(&self, id: u64) -> Option<Operation> {
+            self.operations.get(id)
+        }
+
+        // Helper function to check that the caller is the admin
+        fn ensure_admin(&self) -> Result<(), TimelockError> {
+            if self.env().caller() != self.admin {
+                return Err(TimelockError::NotAdmin);
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::test::{default_accounts, set_block_timestamp, set_callee, set_caller};
+        use ink::env::DefaultEnvironment;
+
+        // Address of the timelock contract in tests
+        const TIMELOCK: [u8; 32] = [0xCC; 32];
+        // Selector of `update_delay`
+        const UPDATE_DELAY: [u8; 4] = ink::selector_bytes!("update_delay");
+
+        // Helper function to deploy a timelock with a one day delay
+        fn setup() -> Timelock {
+            set_callee::<DefaultEnvironment>(AccountId::from(TIMELOCK));
+            Timelock::new(86_400_000)
+        }
+
+        // Test scheduling respects the minimum delay
+        #[ink::test]
+        fn test_schedule() {
+            let mut timelock = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            assert_eq!(
+                timelock.schedule(accounts.bob, UPDATE_DELAY, Vec::new(), 86_399_999),
+                Err(TimelockError::EtaTooEarly)
+            );
+            let id = timelock.schedule(accounts.bob, UPDATE_DELAY, Vec::new(), 86_400_000).unwrap();
+            assert_eq!(timelock.get_operation(id).unwrap().status, OperationStatus::Pending);
+
+            // Only the admin schedules
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(
+                timelock.schedule(accounts.bob, UPDATE_DELAY, Vec::new(), 86_400_000),
+                Err(TimelockError::NotAdmin)
+            );
+        }
+
+        // Test execution before the ETA and cancellation
+        #[ink::test]
+        fn test_execute_before_eta_and_cancel() {
+            let mut timelock = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            let id = timelock.schedule(accounts.bob, UPDATE_DELAY, Vec::new(), 86_400_000).unwrap();
+            set_block_timestamp::<DefaultEnvironment>(86_399_999);
+            assert_eq!(timelock.execute(id), Err(TimelockError::OperationNotReady));
+
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert_eq!(timelock.cancel(id), Err(TimelockError::NotAdmin));
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(timelock.cancel(id).is_ok());
+            set_block_timestamp::<DefaultEnvironment>(86_400_000);
+            assert_eq!(timelock.execute(id), Err(TimelockError::OperationNotPending));
+            assert_eq!(timelock.execute(42), Err(TimelockError::OperationNotFound));
+        }
+
+        // Test the delay can only be changed through the timelock itself
+        #[ink::test]
+        fn test_update_delay() {
+            let mut timelock = setup();
+
+            assert_eq!(timelock.update_delay(0), Err(TimelockError::NotTimelock));
+            set_caller::<DefaultEnvironment>(AccountId::from(TIMELOCK));
+            assert!(timelock.update_delay(3_600_000).is_ok());
+            assert_eq!(timelock.min_delay(), 3_600_000);
+        }
+    }
+
+    // Cross-contract calls cannot be dispatched off-chain, so `execute` is covered here.
+    // Needs a running substrate-contracts-node: `cargo test --features e2e-tests`.
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+        use pidchat_psp22::psp22::Psp22;
+        use pidchat_psp22::token::PidChatPSP22Ref;
+        use pidchat_psp22::PSP22Error;
+        use scale::Encode;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        // Helper function to get an ETA a few seconds ahead of the node clock
+        fn soon() -> u64 {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            u64::try_from(now).unwrap().saturating_add(2_000)
+        }
+
+        // Helper function to wait until an ETA returned by `soon` is reached
+        fn wait_for_eta() {
+            std::thread::sleep(std::time::Duration::from_secs(4));
+        }
+
+        // Test a scheduled `update_delay` is executed through a re-entrant call
+        #[ink_e2e::test]
+        async fn e2e_execute_update_delay(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let timelock = client
+                .instantiate("pidchat_timelock", &ink_e2e::alice(), TimelockRef::new(0), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+
+            let input = 3_600_000u64.encode();
+            let selector = ink::selector_bytes!("update_delay");
+            let schedule = build_message::<TimelockRef>(timelock)
+                .call(|contract| contract.schedule(timelock, selector, input.clone(), soon()));
+            let id = client
+                .call(&ink_e2e::alice(), schedule, 0, None)
+                .await
+                .expect("schedule failed")
+                .return_value()
+                .expect("schedule rejected");
+
+            wait_for_eta();
+            let execute = build_message::<TimelockRef>(timelock).call(|contract| contract.execute(id));
+            client.call(&ink_e2e::bob(), execute, 0, None).await.expect("execute failed");
+
+            let min_delay = build_message::<TimelockRef>(timelock).call(|contract| contract.min_delay());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &min_delay, 0, None).await.return_value(), 3_600_000);
+            let operation = build_message::<TimelockRef>(timelock).call(|contract| contract.get_operation(id));
+            let operation = client.call_dry_run(&ink_e2e::alice(), &operation, 0, None).await.return_value();
+            assert_eq!(operation.unwrap().status, OperationStatus::Executed);
+            Ok(())
+        }
+
+        // Test the token only mints through the timelock once it owns it, and a call the
+        // target rejects fails with `CallFailed`
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_token_admin_through_timelock(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+            let timelock = client
+                .instantiate("pidchat_timelock", &ink_e2e::alice(), TimelockRef::new(0), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let token = client
+                .instantiate("pidchat_psp22", &ink_e2e::alice(), PidChatPSP22Ref::new(1_000_000, None, None, 18), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+
+            // Minting through the timelock fails while it does not own the token
+            let mint_input = (bob, 100u128).encode();
+            let mint_selector = ink::selector_bytes!("mint");
+            let schedule = build_message::<TimelockRef>(timelock)
+                .call(|contract| contract.schedule(token, mint_selector, mint_input.clone(), soon()));
+            let early_mint = client
+                .call(&ink_e2e::alice(), schedule, 0, None)
+                .await
+                .expect("schedule failed")
+                .return_value()
+                .expect("schedule rejected");
+            wait_for_eta();
+            let execute = build_message::<TimelockRef>(timelock).call(|contract| contract.execute(early_mint));
+            let result = client.call_dry_run(&ink_e2e::bob(), &execute, 0, None).await;
+            assert_eq!(result.return_value(), Err(TimelockError::CallFailed));
+
+            // Hand the token over to the timelock
+            let transfer_ownership = build_message::<PidChatPSP22Ref>(token)
+                .call(|contract| contract.transfer_ownership(timelock));
+            client.call(&ink_e2e::alice(), transfer_ownership, 0, None).await.expect("transfer_ownership failed");
+            let accept_selector = ink::selector_bytes!("accept_ownership");
+            let schedule = build_message::<TimelockRef>(timelock)
+                .call(|contract| contract.schedule(token, accept_selector, Vec::new(), soon()));
+            let accept = client
+                .call(&ink_e2e::alice(), schedule, 0, None)
+                .await
+                .expect("schedule failed")
+                .return_value()
+                .expect("schedule rejected");
+            let schedule = build_message::<TimelockRef>(timelock)
+                .call(|contract| contract.schedule(token, mint_selector, mint_input.clone(), soon()));
+            let mint = client
+                .call(&ink_e2e::alice(), schedule, 0, None)
+                .await
+                .expect("schedule failed")
+                .return_value()
+                .expect("schedule rejected");
+            wait_for_eta();
+            for id in [accept, mint] {
+                let execute = build_message::<TimelockRef>(timelock).call(|contract| contract.execute(id));
+                client.call(&ink_e2e::bob(), execute, 0, None).await.expect("execute failed");
+            }
+
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(bob));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 100);
+
+            // The deployer can no longer mint directly
+            let mint = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.mint(bob, 100));
+            let result = client.call_dry_run(&ink_e2e::alice(), &mint, 0, None).await;
+            assert_eq!(result.return_value(), Err(PSP22Error::NotOwner));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,245 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum FactoryError {
+    InstantiationFailed,
+    TooManyTokens,
+}
+
+#[ink::contract]
+pub mod token_factory {
+    use super::FactoryError;
+    use ink::codegen::EmitEvent;
+    use ink::prelude::string::String;
+    use ink::prelude::vec::Vec;
+    use ink::reflect::ContractEventBase;
+    use ink::ToAccountId;
+    use ink_storage::Mapping;
+    use pidchat_psp22::token::PidChatPSP22Ref;
+
+    // Maximum number of tokens registered per creator
+    const MAX_TOKENS_PER_CREATOR: usize = 100;
+
+    /// Deploys `PidChatPSP22` tokens owned by their creator and keeps a registry of them.
+    #[ink(storage)]
+    pub struct TokenFactory {
+        token_code_hash: Hash,
+        tokens_by_creator: Mapping<AccountId, Vec<AccountId>>,
+        token_count: u64,
+    }
+
+    // Define the TokenCreated event
+    #[ink(event)]
+    pub struct TokenCreated {
+        #[ink(topic)]
+        creator: AccountId,
+        #[ink(topic)]
+        token: AccountId,
+        name: String,
+        symbol: String,
+        decimals: u8,
+        supply: Balance,
+    }
+
+    impl TokenFactory {
+        /// Creates a factory deploying the `PidChatPSP22` code uploaded under `token_code_hash`.
+        #[ink(constructor)]
+        pub fn new(token_code_hash: Hash) -> Self {
+            Self {
+                token_code_hash,
+                tokens_by_creator: Mapping::new(),
+                token_count: 0,
+            }
+        }
+
+        /// Deploys a token whose `supply` and admin role go to the caller. `salt` tells apart
+        /// tokens deployed by the same creator with the same parameters. Returns its address.
+        /// Fails with `TooManyTokens` once the creator has `MAX_TOKENS_PER_CREATOR` tokens.
+        #[ink(message)]
+        pub fn create_token(
+            &mut self,
+            name: String,
+            symbol: String,
+            decimals: u8,
+            supply: Balance,
+            salt: Vec<u8>,
+        ) -> Result<AccountId, FactoryError> {
+            let creator = self.env().caller();
+            if self.tokens_of(creator).len() >= MAX_TOKENS_PER_CREATOR {
+                return Err(FactoryError::TooManyTokens);
+            }
+            let token = PidChatPSP22Ref::for_owner(
+                creator,
+                supply,
+                Some(name.clone()),
+                Some(symbol.clone()),
+                decimals,
+            )
+            .code_hash(self.token_code_hash)
+            .endowment(0)
+            .salt_bytes(&salt)
+            .try_instantiate()
+            .map_err(|_| FactoryError::InstantiationFailed)?
+            .map_err(|_| FactoryError::InstantiationFailed)?;
+            let token = token.to_account_id();
+            self.register(creator, token)?;
+            self.emit_event(TokenCreated {
+                creator,
+                token,
+                name,
+                symbol,
+                decimals,
+                supply,
+            });
+            Ok(token)
+        }
+
+        #[ink(message)]
+        pub fn token_code_hash(&self) -> Hash {
+            self.token_code_hash
+        }
+
+        #[ink(message)]
+        pub fn tokens_of(&self, creator: AccountId) -> Vec<AccountId> {
+            self.tokens_by_creator.get(creator).unwrap_or_default()
+        }
+
+        /// Number of tokens created through the factory.
+        #[ink(message)]
+        pub fn token_count(&self) -> u64 {
+            self.token_count
+        }
+
+        // Helper function to record a created token in the registry
+        fn register(&mut self, creator: AccountId, token: AccountId) -> Result<(), FactoryError> {
+            let mut tokens = self.tokens_of(creator);
+            if tokens.len() >= MAX_TOKENS_PER_CREATOR {
+                return Err(FactoryError::TooManyTokens);
+            }
+            tokens.push(token);
+            self.tokens_by_creator.insert(creator, &tokens);
+            self.token_count = self.token_count.saturating_add(1);
+            Ok(())
+        }
+
+        // Helper function to emit events. The token contract linked as a dependency also
+        // implements `EmitEvent`, so the contract has to be named explicitly.
+        fn emit_event<Event>(&self, event: Event)
+        where
+            Event: Into<<TokenFactory as ContractEventBase>::Type>,
+        {
+            EmitEvent::<TokenFactory>::emit_event(self.env(), event);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::test::{default_accounts, set_callee};
+        use ink::env::DefaultEnvironment;
+
+        // Helper function to deploy a factory. Instantiating contracts is not supported by
+        // the off-chain environment of ink 4, so tests cover the registry behind
+        // `create_token`.
+        fn setup() -> TokenFactory {
+            set_callee::<DefaultEnvironment>(AccountId::from([0xFA; 32]));
+            TokenFactory::new(Hash::from([0x42; 32]))
+        }
+
+        // Test created tokens are registered per creator
+        #[ink::test]
+        fn test_registry() {
+            let mut factory = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            assert_eq!(factory.token_code_hash(), Hash::from([0x42; 32]));
+
+            assert!(factory.register(accounts.alice, AccountId::from([0x01; 32])).is_ok());
+            assert!(factory.register(accounts.bob, AccountId::from([0x02; 32])).is_ok());
+            assert!(factory.register(accounts.alice, AccountId::from([0x03; 32])).is_ok());
+            assert_eq!(
+                factory.tokens_of(accounts.alice),
+                ink::prelude::vec![AccountId::from([0x01; 32]), AccountId::from([0x03; 32])]
+            );
+            assert_eq!(factory.tokens_of(accounts.charlie), Vec::new());
+            assert_eq!(factory.token_count(), 3);
+        }
+
+        // Test the number of tokens per creator is bounded
+        #[ink::test]
+        fn test_registry_bounded() {
+            let mut factory = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+
+            for index in 0..MAX_TOKENS_PER_CREATOR {
+                assert!(factory.register(accounts.alice, AccountId::from([index as u8; 32])).is_ok());
+            }
+            assert_eq!(
+                factory.register(accounts.alice, AccountId::from([0xFF; 32])),
+                Err(FactoryError::TooManyTokens)
+            );
+        }
+    }
+
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+
+        use ink_e2e::build_message;
+        use pidchat_psp22::psp22::Psp22;
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+        type Event = <TokenFactory as ContractEventBase>::Type;
+
+        // Test a created token is owned by its creator, registered and announced
+        #[ink_e2e::test(additional_contracts = "../psp22/Cargo.toml")]
+        async fn e2e_create_token(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let code_hash = client
+                .upload("pidchat_psp22", &ink_e2e::alice(), None)
+                .await
+                .expect("upload failed")
+                .code_hash;
+            let factory = client
+                .instantiate("pidchat_token_factory", &ink_e2e::alice(), TokenFactoryRef::new(code_hash), 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+
+            let create = build_message::<TokenFactoryRef>(factory).call(|contract| {
+                contract.create_token(String::from("Group"), String::from("GRP"), 12, 1_000, Vec::new())
+            });
+            let result = client.call(&ink_e2e::alice(), create, 0, None).await.expect("create_token failed");
+
+            // TokenCreated is the event of the factory among those of the instantiation
+            let created = result
+                .events
+                .iter()
+                .filter_map(|event| event.ok())
+                .filter(|event| event.pallet_name() == "Contracts" && event.variant_name() == "ContractEmitted")
+                .filter_map(|event| <(AccountId, Vec<u8>) as scale::Decode>::decode(&mut event.field_bytes()).ok())
+                .filter(|(emitter, _)| *emitter == factory)
+                .find_map(|(_, data)| match <Event as scale::Decode>::decode(&mut &data[..]) {
+                    Ok(Event::TokenCreated(created)) => Some(created),
+                    _ => None,
+                })
+                .expect("TokenCreated event");
+            assert_eq!(created.creator, alice);
+            assert_eq!((created.name.as_str(), created.symbol.as_str()), ("Group", "GRP"));
+            assert_eq!((created.decimals, created.supply), (12, 1_000));
+            let token = created.token;
+
+            let tokens_of = build_message::<TokenFactoryRef>(factory).call(|contract| contract.tokens_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &tokens_of, 0, None).await.return_value(), vec![token]);
+            let balance = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.balance_of(alice));
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &balance, 0, None).await.return_value(), 1_000);
+            let decimals = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.token_decimals());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &decimals, 0, None).await.return_value(), 12);
+            let owner = build_message::<PidChatPSP22Ref>(token).call(|contract| contract.owner());
+            assert_eq!(client.call_dry_run(&ink_e2e::alice(), &owner, 0, None).await.return_value(), Some(alice));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,419 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+#![warn(clippy::arithmetic_side_effects)]
+
+#[ink::contract]
+pub mod wrapped_lunes {
+    use ink::codegen::EmitEvent;
+    use ink::prelude::vec::Vec;
+    use ink::reflect::ContractEventBase;
+    use ink_storage::Mapping;
+    use pidchat_psp22::psp22::Psp22;
+    use pidchat_psp22::{
+        history,
+        HistoryRecord,
+        PSP22Error,
+    };
+
+    /// PSP22 wrapper of native LUNES, backed 1:1 by the native balance of the contract on top
+    /// of the endowment it was instantiated with.
+    ///
+    /// Balances, allowances, history and `Transfer` events behave as in `PidChatPSP22`:
+    /// `transfer_from` consumes the whole allowance, each account keeps its last 100 transfers,
+    /// and deposits, withdrawals and transfers share one id sequence carried by `Transfer`.
+    /// The wrapper has no global transfer log (`transfers_since`) and none of the token's
+    /// extensions such as memos, expiring allowances or escrow.
+    #[ink(storage)]
+    #[derive(Default)]
+    pub struct WrappedLunes {
+        balances: Mapping<AccountId, Balance>,
+        allowances: Mapping<(AccountId, AccountId), Balance>,
+        total_supply: Balance,
+        // Ring buffer of the last `history::MAX_HISTORY_SIZE` records of each account
+        history: Mapping<(AccountId, u32), HistoryRecord>,
+        // Number of records ever appended to each account's history
+        history_count: Mapping<AccountId, u32>,
+        last_transfer_id: u64,
+    }
+
+    // Define the Transfer event
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+        id: u64,
+    }
+
+    // Define the Approval event
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: Balance,
+    }
+
+    // Define the Deposit event
+    #[ink(event)]
+    pub struct Deposit {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    // Define the Withdrawal event
+    #[ink(event)]
+    pub struct Withdrawal {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    impl WrappedLunes {
+        #[ink(constructor)]
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Wraps the transferred native LUNES, minting the same amount to the caller.
+        #[ink(message, payable)]
+        pub fn deposit(&mut self) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let amount = self.env().transferred_value();
+            self.total_supply = self.total_supply.checked_add(amount).ok_or(PSP22Error::Overflow)?;
+            self.balances.insert(caller, &self.balance_of(caller).saturating_add(amount));
+            let id = self.next_transfer_id();
+            self.emit_event(Transfer {
+                from: None,
+                to: Some(caller),
+                value: amount,
+                id,
+            });
+            self.emit_event(Deposit { account: caller, amount });
+            Ok(())
+        }
+
+        /// Burns `amount` wrapped tokens of the caller and sends back as much native LUNES.
+        #[ink(message)]
+        pub fn withdraw(&mut self, amount: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            self.burn_from(caller, amount)?;
+            self.env().transfer(caller, amount).map_err(|_| PSP22Error::TransferFailed)?;
+            self.emit_event(Withdrawal { account: caller, amount });
+            Ok(())
+        }
+
+        // Helper function to burn tokens
+        fn burn_from(&mut self, from: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let balance = self.balance_of(from);
+            if balance < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.balances.insert(from, &balance.saturating_sub(value));
+            self.total_supply = self.total_supply.saturating_sub(value);
+            let id = self.next_transfer_id();
+            self.emit_event(Transfer {
+                from: Some(from),
+                to: None,
+                value,
+                id,
+            });
+            Ok(())
+        }
+
+        // Helper function to move tokens between accounts
+        fn transfer_from_to(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let from_balance = self.balance_of(from);
+            if from_balance < value {
+                return Err(PSP22Error::InsufficientBalance);
+            }
+            self.balances.insert(from, &from_balance.saturating_sub(value));
+            self.balances.insert(to, &self.balance_of(to).saturating_add(value));
+            let id = self.next_transfer_id();
+            let record = HistoryRecord {
+                id,
+                from,
+                to,
+                value,
+                timestamp: self.env().block_timestamp(),
+                memo: Vec::new(),
+            };
+            self.push_history(from, record.clone());
+            if to != from {
+                self.push_history(to, record);
+            }
+            self.emit_event(Transfer {
+                from: Some(from),
+                to: Some(to),
+                value,
+                id,
+            });
+            Ok(())
+        }
+
+        // Helper function to take the next transfer id
+        fn next_transfer_id(&mut self) -> u64 {
+            self.last_transfer_id = self.last_transfer_id.saturating_add(1);
+            self.last_transfer_id
+        }
+
+        // Helper function to append a record to an account's history
+        fn push_history(&mut self, account: AccountId, record: HistoryRecord) {
+            history::push(&mut self.history, &mut self.history_count, account, &record);
+        }
+
+        // Helper function to read an account's history, oldest first
+        fn read_history(&self, account: AccountId) -> Vec<HistoryRecord> {
+            history::read(&self.history, &self.history_count, account)
+        }
+
+        // Helper function to write an allowance and emit the Approval event. Zero allowances
+        // are removed from storage.
+        fn set_allowance(&mut self, owner: AccountId, spender: AccountId, value: Balance) {
+            if value == 0 {
+                self.allowances.remove((owner, spender));
+            } else {
+                self.allowances.insert((owner, spender), &value);
+            }
+            self.emit_event(Approval { owner, spender, value });
+        }
+
+        // Helper function to emit events. The token contract linked as a dependency also
+        // implements `EmitEvent`, so the contract has to be named explicitly.
+        fn emit_event<Event>(&self, event: Event)
+        where
+            Event: Into<<WrappedLunes as ContractEventBase>::Type>,
+        {
+            EmitEvent::<WrappedLunes>::emit_event(self.env(), event);
+        }
+    }
+
+    impl Psp22 for WrappedLunes {
+        #[ink(message)]
+        fn token_name(&self) -> Vec<u8> {
+            b"Wrapped LUNES".to_vec()
+        }
+
+        #[ink(message)]
+        fn token_symbol(&self) -> Vec<u8> {
+            b"WLUNES".to_vec()
+        }
+
+        #[ink(message)]
+        fn token_decimals(&self) -> u8 {
+            8
+        }
+
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            self.total_supply
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(owner).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get((owner, spender)).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            self.transfer_from_to(self.env().caller(), to, value)
+        }
+
+        #[ink(message)]
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(from, caller);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.transfer_from_to(from, to, value)?;
+            // The allowance is consumed by the transfer, as in the token
+            self.set_allowance(from, caller, 0);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            self.set_allowance(self.env().caller(), spender, value);
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            self.set_allowance(caller, spender, self.allowance(caller, spender).saturating_add(value));
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(caller, spender);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.set_allowance(caller, spender, allowance.saturating_sub(value));
+            Ok(())
+        }
+
+        /// Unwraps like `withdraw`: burning without releasing the native funds would break
+        /// the 1:1 backing. Only the caller's own tokens can be burned.
+        #[ink(message)]
+        fn burn(&mut self, from: AccountId, value: Balance) -> Result<(), PSP22Error> {
+            if from != self.env().caller() {
+                return Err(PSP22Error::Unauthorized);
+            }
+            self.withdraw(value)
+        }
+
+        #[ink(message)]
+        fn history(&self, page: u32, limit: u32) -> Vec<HistoryRecord> {
+            let history = self.read_history(self.env().caller());
+            let start = (page.saturating_sub(1) as usize).saturating_mul(limit as usize);
+            if page == 0 || start >= history.len() {
+                return Vec::new();
+            }
+            let end = start.saturating_add(limit as usize).min(history.len());
+            history[start..end].to_vec()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        use ink::env::test::{
+            default_accounts,
+            get_account_balance,
+            set_account_balance,
+            set_callee,
+            set_caller,
+            transfer_in,
+        };
+        use ink::env::DefaultEnvironment;
+
+        type Event = <WrappedLunes as ContractEventBase>::Type;
+
+        // Address of the wrapper contract in tests
+        const WRAPPER: [u8; 32] = [0xEE; 32];
+
+        // Helper function to deploy the wrapper with no native balance
+        fn setup() -> WrappedLunes {
+            set_callee::<DefaultEnvironment>(AccountId::from(WRAPPER));
+            set_account_balance::<DefaultEnvironment>(AccountId::from(WRAPPER), 0);
+            WrappedLunes::new()
+        }
+
+        // Helper function to check the native backing of the supply
+        fn assert_backed(wrapper: &WrappedLunes) {
+            let native = get_account_balance::<DefaultEnvironment>(AccountId::from(WRAPPER)).unwrap();
+            assert_eq!(native, wrapper.total_supply());
+        }
+
+        // Helper function to deposit `amount` from `account`
+        fn deposit(wrapper: &mut WrappedLunes, account: AccountId, amount: Balance) {
+            set_caller::<DefaultEnvironment>(account);
+            transfer_in::<DefaultEnvironment>(amount);
+            assert!(wrapper.deposit().is_ok());
+        }
+
+        // Test deposit and withdraw move native funds 1:1
+        #[ink::test]
+        fn test_deposit_withdraw() {
+            let mut wrapper = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_account_balance::<DefaultEnvironment>(accounts.bob, 1_000);
+
+            deposit(&mut wrapper, accounts.bob, 600);
+            assert_eq!(wrapper.balance_of(accounts.bob), 600);
+            assert_eq!(get_account_balance::<DefaultEnvironment>(accounts.bob), Ok(400));
+
+            assert!(wrapper.withdraw(250).is_ok());
+            assert_eq!(wrapper.balance_of(accounts.bob), 350);
+            assert_eq!(get_account_balance::<DefaultEnvironment>(accounts.bob), Ok(650));
+            assert_eq!(wrapper.withdraw(351), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(wrapper.burn(accounts.alice, 1), Err(PSP22Error::Unauthorized));
+            assert_backed(&wrapper);
+        }
+
+        // Test the native balance equals the supply through deposits, transfers and withdrawals
+        #[ink::test]
+        fn test_backing_invariant() {
+            let mut wrapper = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            for account in [accounts.alice, accounts.bob, accounts.charlie] {
+                set_account_balance::<DefaultEnvironment>(account, 10_000);
+            }
+
+            deposit(&mut wrapper, accounts.alice, 5_000);
+            assert_backed(&wrapper);
+            deposit(&mut wrapper, accounts.bob, 3_000);
+            assert_backed(&wrapper);
+
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(wrapper.transfer(accounts.charlie, 1_500).is_ok());
+            assert!(wrapper.approve(accounts.bob, 1_000).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(wrapper.transfer_from(accounts.alice, accounts.bob, 1_000).is_ok());
+            assert_backed(&wrapper);
+
+            set_caller::<DefaultEnvironment>(accounts.charlie);
+            assert!(wrapper.withdraw(1_500).is_ok());
+            assert_backed(&wrapper);
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(wrapper.burn(accounts.bob, 4_000).is_ok());
+            assert_backed(&wrapper);
+            deposit(&mut wrapper, accounts.charlie, 2_000);
+            assert_backed(&wrapper);
+
+            assert_eq!(wrapper.total_supply(), 4_500);
+            assert_eq!(get_account_balance::<DefaultEnvironment>(accounts.charlie), Ok(9_500));
+        }
+
+        // Test allowances, history and transfer ids follow the token's semantics
+        #[ink::test]
+        fn test_token_semantics() {
+            let mut wrapper = setup();
+            let accounts = default_accounts::<DefaultEnvironment>();
+            set_account_balance::<DefaultEnvironment>(accounts.alice, 10_000);
+            deposit(&mut wrapper, accounts.alice, 5_000);
+
+            // transfer_from consumes the whole allowance
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            assert!(wrapper.approve(accounts.bob, 1_000).is_ok());
+            set_caller::<DefaultEnvironment>(accounts.bob);
+            assert!(wrapper.transfer_from(accounts.alice, accounts.bob, 400).is_ok());
+            assert_eq!(wrapper.allowance(accounts.alice, accounts.bob), 0);
+            assert!(wrapper.allowances.get((accounts.alice, accounts.bob)).is_none());
+
+            // History keeps the last 100 transfers, oldest first
+            set_caller::<DefaultEnvironment>(accounts.alice);
+            for _ in 0..150 {
+                assert!(wrapper.transfer(accounts.charlie, 1).is_ok());
+            }
+            let history = wrapper.history(1, 200);
+            assert_eq!(history.len(), history::MAX_HISTORY_SIZE as usize);
+            assert_eq!(history[0].id, 53);
+            assert_eq!(history[99].id, 152);
+
+            // Deposits and withdrawals share the id sequence of transfers
+            assert!(wrapper.withdraw(1).is_ok());
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            let last = events.iter().rev().find_map(|event| {
+                match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::Transfer(transfer)) => Some(transfer),
+                    _ => None,
+                }
+            });
+            assert_eq!(last.map(|transfer| (transfer.to, transfer.id)), Some((None, 153)));
+        }
+    }
+}